@@ -0,0 +1,81 @@
+#ifndef LGSTER_H
+#define LGSTER_H
+
+/* Generated by cbindgen from src/ffi.rs (see scripts/generate-header.sh). Don't edit it. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// How a call went. Every error is one of the classes of `ErrorClass`, and the values won't change.
+typedef enum LgsterStatus {
+  LGSTER_STATUS_OK = 0,
+  // The command or the arguments are wrong (e.g. a null pointer or an invalid salt)
+  LGSTER_STATUS_INVALID_REQUEST = 1,
+  LGSTER_STATUS_NOT_FOUND = 2,
+  LGSTER_STATUS_UNAUTHORIZED = 3,
+  // Something the call needs is missing (e.g. the client has no keycode)
+  LGSTER_STATUS_NOT_CONFIGURED = 4,
+  // The TV couldn't be reached
+  LGSTER_STATUS_UNREACHABLE = 5,
+  // The TV didn't answer in time
+  LGSTER_STATUS_TIMEOUT = 6,
+  LGSTER_STATUS_INTERNAL = 7,
+} LgsterStatus;
+
+// A TV to send commands to
+typedef struct LgsterClient LgsterClient;
+
+// Creates a client for the TV at `host` and `port` (usually 9761), storing it in `*client`
+// (or null when it fails). The `keycode` can be null, in which case every command fails with
+// `LGSTER_STATUS_NOT_CONFIGURED`, and so can the `salt` (hyphen-separated hex bytes), in which
+// case the usual one is used. The client must be freed with `lgster_client_free`.
+//
+// # Safety
+//
+// The strings must be null or valid nul-terminated strings, and `client` a valid pointer.
+enum LgsterStatus lgster_client_new(const char *host,
+                                    uint16_t port,
+                                    const char *keycode,
+                                    const char *salt,
+                                    struct LgsterClient **client);
+
+// Frees a client made by `lgster_client_new` (doing nothing for null)
+//
+// # Safety
+//
+// The client must be null or one made by `lgster_client_new` that wasn't freed yet.
+void lgster_client_free(struct LgsterClient *client);
+
+// Sends an IP Control command (e.g. `VOLUME_CONTROL 10`) to the TV and stores its reply
+// (e.g. `OK`, without the trailing line feed) in `*reply`, or null when it fails. The reply
+// must be freed with `lgster_string_free`.
+//
+// # Safety
+//
+// The client must be one made by `lgster_client_new`, `command` a valid nul-terminated
+// string and `reply` a valid pointer.
+enum LgsterStatus lgster_send(const struct LgsterClient *client, const char *command, char **reply);
+
+// Sends the wake-on-lan packet for `mac_address` (e.g. `a8:23:fe:01:02:03`) to `target`, which
+// is usually a broadcast address (and 255.255.255.255 when it's null)
+//
+// # Safety
+//
+// The strings must be null (only `target`) or valid nul-terminated strings.
+enum LgsterStatus lgster_wake(const char *mac_address, const char *target);
+
+// Frees a string returned by lgster (doing nothing for null)
+//
+// # Safety
+//
+// The string must be null or one returned by lgster that wasn't freed yet.
+void lgster_string_free(char *string);
+
+// The message of the last error of the calling thread, or null if its last call succeeded.
+// It belongs to lgster and is only valid until the next call on the same thread.
+const char *lgster_last_error(void);
+
+#endif /* LGSTER_H */
@@ -0,0 +1,284 @@
+//! The IP Control protocol of LG TVs, without sockets, randomness or even std, so that it can
+//! run on microcontrollers. Every message is a 16 byte IV encrypted with AES-128 ECB, followed
+//! by the message encrypted with AES-128 CBC and that IV, with a key derived from the keycode.
+//! The IVs are up to the caller, which must make them random.
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use aes::cipher::block_padding::{NoPadding, Pkcs7};
+use aes::cipher::{BlockDecrypt, BlockDecryptMut, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit};
+use aes::Aes128;
+use core::convert::TryInto;
+use core::fmt;
+use sha2::Sha256;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+pub const DEFAULT_PORT: u16 = 9761;
+/// The salt every TV uses to derive the key from its keycode
+pub const DEFAULT_SALT: [u8; 16] = [
+    0x63, 0x61, 0xb8, 0x0e, 0x9b, 0xdc, 0xa6, 0x63, 0x8d, 0x07, 0x20, 0xf2, 0xcc, 0x56, 0x8f, 0xb9,
+];
+
+const BLOCK_SIZE: usize = 16;
+const KEY_DERIVATION_ITERATIONS: u32 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The buffer given to write the message to can't hold it (see `request_len` and `reply_len`)
+    BufferTooSmall,
+    /// The message isn't an IV followed by at least one block
+    InvalidLength,
+    /// The message doesn't decrypt to a command, which usually means it was encrypted with another key
+    NotACommand,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BufferTooSmall => write!(f, "The buffer is too small for the message"),
+            Error::InvalidLength => write!(f, "The message should be a whole number of 16 byte blocks (at least 2)"),
+            Error::NotACommand => write!(f, "The message isn't a command (was it encrypted with another keycode?)"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// The AES key of a keycode (PBKDF2 with HMAC-SHA256), which takes a noticeable while,
+/// so it's worth doing only once per keycode
+pub fn derive_key(keycode: &str, salt: &[u8; 16]) -> [u8; 16] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 16>(keycode.as_bytes(), salt, KEY_DERIVATION_ITERATIONS)
+}
+
+pub fn encrypt_iv(iv: &[u8; 16], key: &[u8; 16]) -> [u8; 16] {
+    let mut block = (*iv).into();
+    Aes128::new(key.into()).encrypt_block(&mut block);
+    block.into()
+}
+
+pub fn decrypt_iv(encrypted_iv: &[u8; 16], key: &[u8; 16]) -> [u8; 16] {
+    let mut block = (*encrypted_iv).into();
+    Aes128::new(key.into()).decrypt_block(&mut block);
+    block.into()
+}
+
+/// How long the request for a command of `command_len` bytes is
+pub const fn request_len(command_len: usize) -> usize {
+    // the carriage return, then at least one byte of padding
+    BLOCK_SIZE + ((command_len + 1) / BLOCK_SIZE + 1) * BLOCK_SIZE
+}
+
+/// How long the reply of a TV with `reply_len` bytes is
+pub const fn reply_len(reply_len: usize) -> usize {
+    // the line feed, then zeros up to the end of the block (if any)
+    BLOCK_SIZE + (reply_len + 1).div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Writes the request for a command (e.g. `VOLUME_CONTROL 10`) to the start of `buffer`,
+/// returning its length (see `request_len`)
+pub fn encrypt_command(command: &str, iv: &[u8; 16], key: &[u8; 16], buffer: &mut [u8]) -> Result<usize, Error> {
+    let len = request_len(command.len());
+    let (encrypted_iv, message) = buffer.get_mut(..len).ok_or(Error::BufferTooSmall)?.split_at_mut(BLOCK_SIZE);
+    encrypted_iv.copy_from_slice(&encrypt_iv(iv, key));
+
+    message[..command.len()].copy_from_slice(command.as_bytes());
+    message[command.len()] = b'\r';
+    cbc::Encryptor::<Aes128>::new(key.into(), iv.into())
+        .encrypt_padded_mut::<Pkcs7>(message, command.len() + 1)
+        .map_err(|_| Error::BufferTooSmall)?;
+    Ok(len)
+}
+
+/// Decrypts a request (e.g. of another controller) in place, returning its command
+pub fn decrypt_command<'a>(request: &'a mut [u8], key: &[u8; 16]) -> Result<&'a [u8], Error> {
+    let message: &'a [u8] = decrypt_message(request, key)?;
+    // PKCS#7 padding, then the carriage return
+    let padding = message[message.len() - 1] as usize;
+    if padding == 0 || padding > BLOCK_SIZE {
+        return Err(Error::NotACommand);
+    }
+    let (command, padding_bytes) = message.split_at(message.len() - padding);
+    if padding_bytes.iter().any(|b| *b as usize != padding) {
+        return Err(Error::NotACommand);
+    }
+    command.strip_suffix(b"\r").ok_or(Error::NotACommand)
+}
+
+/// Writes a reply (e.g. `OK`) to the start of `buffer` the way the TVs do, returning its
+/// length (see `reply_len`)
+pub fn encrypt_reply(reply: &str, iv: &[u8; 16], key: &[u8; 16], buffer: &mut [u8]) -> Result<usize, Error> {
+    let len = reply_len(reply.len());
+    let (encrypted_iv, message) = buffer.get_mut(..len).ok_or(Error::BufferTooSmall)?.split_at_mut(BLOCK_SIZE);
+    encrypted_iv.copy_from_slice(&encrypt_iv(iv, key));
+
+    message.fill(0);
+    message[..reply.len()].copy_from_slice(reply.as_bytes());
+    message[reply.len()] = b'\n';
+    let message_len = message.len();
+    cbc::Encryptor::<Aes128>::new(key.into(), iv.into())
+        .encrypt_padded_mut::<NoPadding>(message, message_len)
+        .map_err(|_| Error::BufferTooSmall)?;
+    Ok(len)
+}
+
+/// Decrypts the response of a TV in place, returning its reply (up to the zeros that pad it,
+/// but with its line feed)
+pub fn decrypt_reply<'a>(response: &'a mut [u8], key: &[u8; 16]) -> Result<&'a [u8], Error> {
+    let message = decrypt_message(response, key)?;
+    let end = message.iter().position(|b| *b == 0).unwrap_or(message.len());
+    Ok(&message[..end])
+}
+
+/// Decrypts the message after the IV in place, without removing any padding
+fn decrypt_message<'a>(message: &'a mut [u8], key: &[u8; 16]) -> Result<&'a mut [u8], Error> {
+    if message.len() < 2 * BLOCK_SIZE || message.len() % BLOCK_SIZE != 0 {
+        return Err(Error::InvalidLength);
+    }
+
+    let (encrypted_iv, message) = message.split_at_mut(BLOCK_SIZE);
+    // this is safe to unwrap because it was split at the block size
+    let iv = decrypt_iv(encrypted_iv[..].try_into().unwrap(), key);
+    cbc::Decryptor::<Aes128>::new(key.into(), &iv.into())
+        .decrypt_padded_mut::<NoPadding>(message)
+        .map_err(|_| Error::InvalidLength)?;
+    Ok(message)
+}
+
+/// `encrypt_command` into a Vec of its own
+#[cfg(feature = "alloc")]
+pub fn encrypt_command_to_vec(command: &str, iv: &[u8; 16], key: &[u8; 16]) -> Vec<u8> {
+    let mut request = alloc::vec![0; request_len(command.len())];
+    // it can't fail, since the buffer has the right size
+    encrypt_command(command, iv, key, &mut request).unwrap();
+    request
+}
+
+/// `encrypt_reply` into a Vec of its own
+#[cfg(feature = "alloc")]
+pub fn encrypt_reply_to_vec(reply: &str, iv: &[u8; 16], key: &[u8; 16]) -> Vec<u8> {
+    let mut response = alloc::vec![0; reply_len(reply.len())];
+    // it can't fail, since the buffer has the right size
+    encrypt_reply(reply, iv, key, &mut response).unwrap();
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+    use std::vec::Vec;
+
+    // the vectors were made with another AES and PBKDF2 implementation (Python's cryptography and hashlib)
+    const KEYCODE: &str = "ABCDEFGH";
+    const KEY: &str = "ba31dfcfc0f5631f72753e12c2f62e82";
+    const IV: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    const ENCRYPTED_IV: &str = "8c29585e93dad761ea61341836286694";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| std::format!("{:02x}", byte)).collect()
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn key() -> [u8; 16] {
+        derive_key(KEYCODE, &DEFAULT_SALT)
+    }
+
+    fn encrypted_command(command: &str) -> String {
+        let mut buffer = [0; 64];
+        let len = encrypt_command(command, &IV, &key(), &mut buffer).unwrap();
+        assert_eq!(len, request_len(command.len()));
+        hex(&buffer[..len])
+    }
+
+    #[test]
+    fn derives_the_key_of_a_keycode() {
+        assert_eq!(hex(&key()), KEY);
+    }
+
+    #[test]
+    fn encrypts_the_iv_with_ecb() {
+        assert_eq!(hex(&encrypt_iv(&IV, &key())), ENCRYPTED_IV);
+        assert_eq!(decrypt_iv(&encrypt_iv(&IV, &key()), &key()), IV);
+    }
+
+    #[test]
+    fn encrypts_commands() {
+        assert_eq!(
+            encrypted_command("CURRENT_VOL"),
+            "8c29585e93dad761ea61341836286694e6203b5da9c7937180aac92a1d421c7d"
+        );
+        assert_eq!(
+            encrypted_command("VOLUME_CONTROL 10"),
+            "8c29585e93dad761ea6134183628669452bb94e7696dd10ba451787da1a565e4\
+             f8802640ab5090180b3bf67e56a43c11"
+        );
+    }
+
+    #[test]
+    fn encrypts_commands_that_fill_a_block() {
+        // with the carriage return it's exactly a block, so the padding is a whole block
+        assert_eq!(
+            encrypted_command("VOLUME_MUTE off"),
+            "8c29585e93dad761ea6134183628669481cb811a2357ff8749332d1d6821765d\
+             faa81594d7321b5fb68323c7198c4d49"
+        );
+        assert_eq!(
+            encrypted_command("PICTURE_MODE std"),
+            "8c29585e93dad761ea61341836286694827b2cc0f4e31f602d8068b453c4c2eb\
+             6f48ecae5f5141d72f0a9f2c5159f419"
+        );
+    }
+
+    #[test]
+    fn decrypts_the_commands_it_encrypted() {
+        for command in ["CURRENT_VOL", "VOLUME_MUTE off", "PICTURE_MODE std", "VOLUME_CONTROL 10"] {
+            let mut buffer = [0; 64];
+            let len = encrypt_command(command, &IV, &key(), &mut buffer).unwrap();
+            assert_eq!(decrypt_command(&mut buffer[..len], &key()), Ok(command.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn decrypts_replies() {
+        let mut response = unhex(
+            "8c29585e93dad761ea613418362866943a189800c39195e65264d6b2dd770cb8",
+        );
+        assert_eq!(decrypt_reply(&mut response, &key()), Ok(&b"VOL:5\n"[..]));
+        // with the line feed it's exactly a block, so there are no zeros after it
+        let mut response = unhex(
+            "8c29585e93dad761ea61341836286694cad02dbbccca89cca7835f7eb94c3854",
+        );
+        assert_eq!(decrypt_reply(&mut response, &key()), Ok(&b"MAC:a8:23:fe:01\n"[..]));
+    }
+
+    #[test]
+    fn replies_round_trip() {
+        for reply in ["OK", "VOL:5", "MAC:a8:23:fe:01", "MAC:a8:23:fe:01:02:03"] {
+            let mut buffer = [0; 64];
+            let len = encrypt_reply(reply, &IV, &key(), &mut buffer).unwrap();
+            assert_eq!(len, reply_len(reply.len()));
+            let decrypted = decrypt_reply(&mut buffer[..len], &key()).unwrap();
+            assert_eq!(decrypted.strip_suffix(b"\n"), Some(reply.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_message() {
+        assert_eq!(decrypt_reply(&mut [0; 16], &key()), Err(Error::InvalidLength));
+        assert_eq!(decrypt_reply(&mut [0; 40], &key()), Err(Error::InvalidLength));
+        assert_eq!(encrypt_command("CURRENT_VOL", &IV, &key(), &mut [0; 16]), Err(Error::BufferTooSmall));
+        // a command encrypted with another keycode doesn't have valid padding (or a carriage return)
+        let mut buffer = [0; 64];
+        let len = encrypt_command("CURRENT_VOL", &IV, &derive_key("HGFEDCBA", &DEFAULT_SALT), &mut buffer).unwrap();
+        assert_eq!(decrypt_command(&mut buffer[..len], &key()), Err(Error::NotACommand));
+    }
+}
@@ -0,0 +1,88 @@
+"""Send IP Control commands to LG TVs (the Python bindings of lgster)."""
+
+from os import PathLike
+from typing import Dict, List, Optional, Union
+
+class LgsterError(Exception):
+    """Any error of lgster."""
+
+class InvalidRequestError(LgsterError):
+    """The command or its parameters are wrong (or the TV refused them)."""
+
+class NotFoundError(LgsterError):
+    """There's no such command, action, TV profile or group."""
+
+class UnauthorizedError(LgsterError):
+    """The request didn't bring the right credentials."""
+
+class NotConfiguredError(LgsterError):
+    """The TV lacks something the command needs (e.g. a keycode or a MAC address)."""
+
+class UnreachableError(LgsterError):
+    """The TV couldn't be reached."""
+
+class TimeoutError(LgsterError):
+    """The TV didn't answer in time."""
+
+class InternalError(LgsterError):
+    """Anything else."""
+
+class Client:
+    """A TV to send commands to."""
+
+    def __init__(
+        self,
+        host: str,
+        keycode: Optional[str] = None,
+        *,
+        port: int = 9761,
+        salt: Optional[str] = None,
+        mac: Optional[str] = None,
+        wake_target: Optional[str] = None,
+    ) -> None:
+        """A TV given by its host (the MAC address is only needed to wake it up)."""
+
+    @staticmethod
+    def from_profile(name: str, config: Optional[Union[str, PathLike]] = None) -> "Client":
+        """A TV profile of the config file (~/.config/lgster/config.toml by default)."""
+
+    @property
+    def host(self) -> str: ...
+    @property
+    def port(self) -> int: ...
+    def send(self, command: str) -> str:
+        """Sends a raw IP Control command (e.g. `VOLUME_CONTROL 10`), returning the reply."""
+
+    def run(self, command: str, action: str, *parameters: str) -> List[str]:
+        """Runs any command of the lgster CLI (e.g. `run("input", "hdmi-1")`), returning its replies."""
+
+    def query_volume(self) -> int: ...
+    def query_mute(self) -> bool: ...
+    def query_current_app(self) -> str:
+        """The friendly name of the app (e.g. `netflix`) or, when there's none, its id."""
+
+    def query_mac_addresses(self) -> Dict[str, Optional[str]]:
+        """The MAC addresses of the wired and wifi interfaces, as `{"wired": ..., "wifi": ...}`."""
+
+    def set_volume(self, volume: int) -> None: ...
+    def set_mute(self, muted: bool) -> None: ...
+    def set_backlight(self, backlight: int) -> None: ...
+    def set_input(self, input: str) -> None:
+        """An input (e.g. `hdmi-1`) of `lgster input`."""
+
+    def launch_app(self, app: str) -> None:
+        """An app by its friendly name (e.g. `netflix`) or its id."""
+
+    def set_channel(self, channel: str) -> None:
+        """A channel (e.g. `7-1`) or, for TV profiles, one of its favourites."""
+
+    def press_key(self, key: str) -> None:
+        """A key of the remote (e.g. `ok` or `vol-up`) of `lgster key`."""
+
+    def power_off(self) -> None: ...
+    def wake(self, wait: Optional[float] = None) -> Optional[float]:
+        """Sends the wake-on-lan packet and, with `wait` (in seconds), waits until the TV answers,
+        returning how long that took."""
+
+def wake(mac: str, target: Optional[str] = None) -> None:
+    """Sends the wake-on-lan packet for a MAC address without a `Client`."""
@@ -0,0 +1,6 @@
+#!/bin/sh
+# Regenerates include/lgster.h, the C header of the ffi feature, after changing the C API (src/ffi.rs).
+# Needs cbindgen (cargo install cbindgen).
+set -e
+cd "$(dirname "$0")/.."
+cbindgen --config cbindgen.toml --output include/lgster.h
@@ -0,0 +1,255 @@
+use log::debug;
+use std::error::Error;
+use std::fmt;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::apps::{app_id_from_reply, AppCatalog};
+use crate::channel::Channel;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::target::Target;
+use crate::wake::{wake_up, wake_up_and_wait};
+
+const CHANNEL_SET_USAGE: &str = "channel set <channel or favourite>";
+
+#[derive(Debug)]
+pub struct UsageError {
+    usage: &'static str,
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Usage: {}", self.usage)
+    }
+}
+
+impl Error for UsageError {}
+
+impl UsageError {
+    pub fn new(usage: &'static str) -> Self {
+        UsageError { usage }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownActionError {
+    command_name: String,
+    action: String,
+}
+
+impl fmt::Display for UnknownActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "There's no action {} for {}", self.action, self.command_name)
+    }
+}
+
+impl Error for UnknownActionError {}
+
+impl UnknownActionError {
+    pub fn new(command_name: &str, action: &str) -> Self {
+        UnknownActionError {
+            command_name: command_name.to_string(),
+            action: action.to_string(),
+        }
+    }
+}
+
+/// Runs an action of the command catalog on a single TV, returning the first line of each reply.
+/// Only the actions that talk to the TV are run here (e.g. not editing favourite channels).
+pub fn run_action(
+    command: &Command,
+    action: &str,
+    parameters: &[String],
+    target: &Target,
+    config: &Config,
+    app_catalog: &AppCatalog,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    debug!("running {} {} {:?} on target={}", command.command_name, action, parameters, target.name);
+    let send = |command: String| target.send(command).map(|response| first_line(&response));
+
+    if (command.command_name, action) == ("channel", "set") {
+        let channel = resolve_channel(parameters, target, config)?;
+        return Ok(vec![set_channel(send, channel)?]);
+    }
+    if (command.command_name, action) == ("power", "on") {
+        return Ok(vec![power_on(parameters, target, config)?]);
+    }
+
+    let command_actions = command
+        .command_actions
+        .iter()
+        .find(|a| a.0 == action)
+        .ok_or_else(|| UnknownActionError::new(command.command_name, action))?;
+
+    let mut responses = vec![];
+    for (i, action_to_run) in command_actions.1.iter().enumerate() {
+        // TODO: test first if the command has parameters to give friendly error
+
+        let action_to_run = match parameters {
+            [] => action_to_run.to_string(),
+            [el] if command.command_name == "app" => action_to_run.replace("{}", app_catalog.resolve(el)),
+            [el] => action_to_run.replace("{}", el),
+            // TODO: think about how to generically treat multiple patterns
+            _ => return Err(Box::new(UsageError::new("commands take at most one parameter"))),
+        };
+
+        if i > 0 {
+            sleep(Duration::from_millis(200));
+        }
+        let response = send(action_to_run)?;
+
+        if (command.command_name, action) == ("query", "current-app") {
+            let app_id = app_id_from_reply(&response);
+            responses.push(app_catalog.friendly_name(app_id).unwrap_or(app_id).to_string());
+        } else {
+            responses.push(response);
+        }
+    }
+    Ok(responses)
+}
+
+/// Checks an action and its parameters as far as it can be done without a TV, so that a mistake
+/// in something that only runs later on (e.g. a schedule) shows right away
+pub fn check_action(command: &Command, action: &str, parameters: &[String]) -> Result<(), Box<dyn Error>> {
+    match (command.command_name, action, parameters) {
+        ("channel", "set", [_]) => return Ok(()),
+        ("channel", "set", _) => return Err(Box::new(UsageError::new(CHANNEL_SET_USAGE))),
+        ("power", "on", _) => return power_on_timeout(parameters).map(|_| ()),
+        _ => {}
+    }
+
+    let command_actions = command
+        .command_actions
+        .iter()
+        .find(|a| a.0 == action)
+        .ok_or_else(|| UnknownActionError::new(command.command_name, action))?;
+    let takes_parameter = command_actions.1.iter().any(|command| command.contains("{}"));
+    match (takes_parameter, parameters) {
+        (true, [_]) | (false, []) => Ok(()),
+        (true, _) => Err(format!("{} {} takes exactly one parameter", command.command_name, action).into()),
+        (false, _) => Err(format!("{} {} takes no parameters", command.command_name, action).into()),
+    }
+}
+
+fn power_on(parameters: &[String], target: &Target, config: &Config) -> Result<String, Box<dyn Error>> {
+    match power_on_timeout(parameters)? {
+        Some(timeout) => {
+            let took = wake_up_and_wait(target, config, timeout)?;
+            Ok(format!("OK (answered after {:.1}s)", took.as_secs_f64()))
+        }
+        None => {
+            wake_up(target, config)?;
+            Ok("OK".to_string())
+        }
+    }
+}
+
+/// How long `power on` waits for the TV to answer, if at all
+fn power_on_timeout(parameters: &[String]) -> Result<Option<Duration>, Box<dyn Error>> {
+    let usage = || Box::new(UsageError::new("power on [--wait [SECONDS]]"));
+    match parameters {
+        [] => Ok(None),
+        [wait] if wait == "--wait" => Ok(Some(Duration::from_secs(60))),
+        // negative, infinite or too many seconds too
+        [wait, seconds] if wait == "--wait" => match seconds.parse().map(Duration::try_from_secs_f64) {
+            Ok(Ok(timeout)) => Ok(Some(timeout)),
+            _ => Err(usage()),
+        },
+        _ => Err(usage()),
+    }
+}
+
+fn first_line(response: &str) -> String {
+    response.chars().take_while(|c| *c != '\n').collect()
+}
+
+/// The parameter of `channel set` may be either a channel or a favourite of the TV profile
+fn resolve_channel(
+    parameters: &[String],
+    target: &Target,
+    config: &Config,
+) -> Result<Channel, Box<dyn Error>> {
+    let channel = match parameters {
+        [channel] => channel,
+        _ => return Err(Box::new(UsageError::new(CHANNEL_SET_USAGE))),
+    };
+
+    let favourite = target
+        .profile
+        .as_ref()
+        .and_then(|profile| config.tvs[profile].channels.get(channel));
+
+    Ok(favourite.unwrap_or(channel).parse()?)
+}
+
+fn set_channel(
+    send: impl Fn(String) -> Result<String, Box<dyn Error>>,
+    channel: Channel,
+) -> Result<String, Box<dyn Error>> {
+    // a TV that couldn't be reached (or didn't answer) wouldn't take the keys either
+    let response = send(channel.set_command())?;
+    if response == "OK" {
+        return Ok(response);
+    }
+
+    // not every TV knows how to tune directly, so one that refuses it gets the channel typed just like
+    // we would on the remote
+    let mut response = String::new();
+    for key in channel.key_sequence() {
+        sleep(Duration::from_millis(200));
+        response = send(key)?;
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::{Error as IOError, ErrorKind};
+
+    /// Answers the commands with `answer`, keeping them
+    fn fake_tv<'a>(
+        sent: &'a RefCell<Vec<String>>,
+        answer: impl Fn(&str) -> Result<String, Box<dyn Error>> + 'a,
+    ) -> impl Fn(String) -> Result<String, Box<dyn Error>> + 'a {
+        move |command| {
+            let response = answer(&command);
+            sent.borrow_mut().push(command);
+            response
+        }
+    }
+
+    #[test]
+    fn tunes_directly_when_the_tv_can() {
+        let sent = RefCell::new(vec![]);
+        let response = set_channel(fake_tv(&sent, |_| Ok("OK".to_string())), "7-1".parse().unwrap());
+        assert_eq!(response.unwrap(), "OK");
+        assert_eq!(*sent.borrow(), ["CHANNEL_SET 7-1"]);
+    }
+
+    #[test]
+    fn types_the_channel_when_the_tv_refuses_to_tune() {
+        let sent = RefCell::new(vec![]);
+        let answer = |command: &str| match command.starts_with("CHANNEL_SET") {
+            true => Ok("NG".to_string()),
+            false => Ok("OK".to_string()),
+        };
+        let response = set_channel(fake_tv(&sent, answer), "12".parse().unwrap());
+        assert_eq!(response.unwrap(), "OK");
+        assert_eq!(
+            *sent.borrow(),
+            ["CHANNEL_SET 12", "KEY_ACTION number1", "KEY_ACTION number2", "KEY_ACTION ok"]
+        );
+    }
+
+    #[test]
+    fn doesnt_type_the_channel_when_the_tv_cant_be_reached() {
+        let sent = RefCell::new(vec![]);
+        let answer = |_: &str| Err(Box::new(IOError::new(ErrorKind::TimedOut, "timed out")) as Box<dyn Error>);
+        let error = set_channel(fake_tv(&sent, answer), "7-1".parse().unwrap()).unwrap_err();
+        assert_eq!(error.downcast_ref::<IOError>().unwrap().kind(), ErrorKind::TimedOut);
+        assert_eq!(*sent.borrow(), ["CHANNEL_SET 7-1"]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+/// webOS app ids known to work with `APP_LAUNCH`, by friendly name.
+/// When more than one name points to the same id, the first one is used for reverse lookups.
+const BUNDLED_APPS: &[(&str, &str)] = &[
+    ("hdmi-1", "com.webos.app.hdmi1"),
+    ("hdmi-2", "com.webos.app.hdmi2"),
+    ("hdmi-3", "com.webos.app.hdmi3"),
+    ("hdmi-4", "com.webos.app.hdmi4"),
+    ("live-tv", "com.webos.app.livetv"),
+    ("browser", "com.webos.app.browser"),
+    ("settings", "com.palm.app.settings"),
+    ("netflix", "netflix"),
+    ("youtube", "youtube.leanback.v4"),
+    ("prime", "amazon"),
+    ("disney", "com.disney.disneyplus-prod"),
+    ("plex", "cdp-30"),
+    ("hulu", "hulu"),
+    ("spotify", "spotify-beehive"),
+    ("apple-tv", "com.apple.appletv"),
+];
+
+#[derive(Debug, Clone)]
+pub struct AppCatalog {
+    // kept as a list instead of a map so that user entries can shadow
+    // the bundled ones while still being found first on reverse lookups
+    apps: Vec<(String, String)>,
+}
+
+impl AppCatalog {
+    pub fn bundled() -> Self {
+        AppCatalog {
+            apps: BUNDLED_APPS
+                .iter()
+                .map(|(name, id)| (name.to_string(), id.to_string()))
+                .collect(),
+        }
+    }
+
+    /// The bundled catalog extended (and possibly overridden) by the `[apps]` table of the config.
+    pub fn with_user_apps(user_apps: &BTreeMap<String, String>) -> Self {
+        let mut catalog = AppCatalog::bundled();
+        catalog.apps.retain(|(name, _)| !user_apps.contains_key(name));
+
+        let mut apps: Vec<(String, String)> = user_apps
+            .iter()
+            .map(|(name, id)| (name.clone(), id.clone()))
+            .collect();
+        apps.append(&mut catalog.apps);
+
+        AppCatalog { apps }
+    }
+
+    /// Resolves a friendly name to its app id. Anything that is not a known name
+    /// is assumed to already be an app id and is returned untouched.
+    pub fn resolve<'a>(&'a self, name_or_id: &'a str) -> &'a str {
+        self.apps
+            .iter()
+            .find(|(name, _)| name == name_or_id)
+            .map(|(_, id)| id.as_str())
+            .unwrap_or(name_or_id)
+    }
+
+    pub fn friendly_name(&self, app_id: &str) -> Option<&str> {
+        self.apps
+            .iter()
+            .find(|(_, id)| id == app_id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.apps.iter().map(|(name, id)| (name.as_str(), id.as_str()))
+    }
+}
+
+/// Extracts the app id out of a `CURRENT_APP` reply, which may or may not
+/// come prefixed by a tag (e.g. `APP:com.webos.app.hdmi1`).
+pub fn app_id_from_reply(reply: &str) -> &str {
+    let reply = reply.trim();
+    match reply.find(':') {
+        Some(i) if reply[..i].chars().all(|c| c.is_ascii_uppercase() || c == '_') => {
+            reply[i + 1..].trim()
+        }
+        _ => reply,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_apps(apps: &[(&str, &str)]) -> BTreeMap<String, String> {
+        apps.iter().map(|(name, id)| (name.to_string(), id.to_string())).collect()
+    }
+
+    #[test]
+    fn resolves_bundled_names() {
+        let catalog = AppCatalog::bundled();
+        assert_eq!(catalog.resolve("prime"), "amazon");
+        assert_eq!(catalog.friendly_name("amazon"), Some("prime"));
+        assert_eq!(catalog.friendly_name("com.webos.app.hdmi2"), Some("hdmi-2"));
+    }
+
+    #[test]
+    fn passes_unknown_names_through_as_ids() {
+        let catalog = AppCatalog::with_user_apps(&user_apps(&[("crunchy", "crunchyroll.watch")]));
+        assert_eq!(catalog.resolve("com.webos.app.browser"), "com.webos.app.browser");
+        assert_eq!(catalog.resolve("some.unknown.app"), "some.unknown.app");
+        assert_eq!(catalog.friendly_name("some.unknown.app"), None);
+    }
+
+    #[test]
+    fn user_apps_extend_and_override_the_bundled_ones() {
+        let catalog = AppCatalog::with_user_apps(&user_apps(&[
+            ("crunchy", "crunchyroll.watch"),
+            ("prime", "amazon.de"),
+            ("tv", "com.webos.app.livetv"),
+        ]));
+        assert_eq!(catalog.resolve("crunchy"), "crunchyroll.watch");
+        assert_eq!(catalog.resolve("prime"), "amazon.de");
+        assert_eq!(catalog.resolve("netflix"), "netflix");
+        // the overridden id isn't known by the bundled name anymore
+        assert_eq!(catalog.friendly_name("amazon"), None);
+        // and the user's name comes first on reverse lookups
+        assert_eq!(catalog.friendly_name("com.webos.app.livetv"), Some("tv"));
+        assert_eq!(catalog.iter().filter(|(name, _)| *name == "prime").count(), 1);
+    }
+
+    #[test]
+    fn reads_app_ids_from_replies() {
+        assert_eq!(app_id_from_reply("APP:com.webos.app.hdmi1"), "com.webos.app.hdmi1");
+        assert_eq!(app_id_from_reply("CURRENT_APP: netflix \n"), "netflix");
+        assert_eq!(app_id_from_reply("netflix\n"), "netflix");
+        // a colon that isn't after a tag is part of the id
+        assert_eq!(app_id_from_reply("com.example:tv"), "com.example:tv");
+    }
+
+    #[test]
+    fn passes_unexpected_replies_through() {
+        assert_eq!(app_id_from_reply("NG"), "NG");
+        assert_eq!(app_id_from_reply(""), "");
+    }
+}
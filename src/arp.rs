@@ -0,0 +1,53 @@
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+// the kernel's ATF_COM flag, meaning the entry is complete (the neighbour answered)
+const COMPLETE_ENTRY_FLAG: u32 = 0x2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpEntry {
+    pub ip: Ipv4Addr,
+    pub mac_address: String,
+    pub interface: String,
+}
+
+/// Reads the complete entries of the kernel's neighbour table (Linux only)
+pub fn read_arp_table() -> io::Result<Vec<ArpEntry>> {
+    let contents = fs::read_to_string(ARP_TABLE_PATH)?;
+
+    // IP address       HW type     Flags       HW address            Mask     Device
+    // 192.168.1.50     0x1         0x2         a8:23:fe:01:02:03     *        eth0
+    Ok(contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [ip, _, flags, mac_address, _, interface] => {
+                    let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).ok()?;
+                    if flags & COMPLETE_ENTRY_FLAG == 0 {
+                        return None;
+                    }
+
+                    Some(ArpEntry {
+                        ip: ip.parse().ok()?,
+                        mac_address: mac_address.to_string(),
+                        interface: interface.to_string(),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+/// The MAC address the kernel last saw for `ip`, if any
+pub fn mac_address_of(ip: Ipv4Addr) -> Option<String> {
+    read_arp_table()
+        .ok()?
+        .into_iter()
+        .find(|entry| entry.ip == ip)
+        .map(|entry| entry.mac_address)
+}
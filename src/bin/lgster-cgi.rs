@@ -1,23 +1,29 @@
+use std::env;
 use std::error::Error;
+use std::path::PathBuf;
 use cgi::handle;
-use http::header;
 use http::response;
 use http::request;
 
-fn create_response<S>(content_type: &str, body: S) -> response::Response<Vec<u8>>
-    where S: Into<String>
-{
-    let body: Vec<u8> = body.into().into_bytes();
-    response::Builder::new()
-        .status(200)
-        .header(header::CONTENT_TYPE, content_type)
-        .header(header::CONTENT_LENGTH, format!("{}", body.len()).as_str())
-        .body(body)
-        .unwrap()
-}
+use lgster::config::Config;
+use lgster::rest::{error_response, handle_request};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    Ok(handle(|_request: request::Request<Vec<u8>>| -> response::Response<Vec<u8>> {
-        create_response("application/json; charset=utf-8", "{}")
-    }))
+    // the web server usually runs as a user of its own, so the config file can be given explicitly
+    let config_path = env::var_os("LGSTER_CONFIG").map(PathBuf::from).or_else(Config::default_path);
+    let config = match config_path {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    };
+
+    handle(|request: request::Request<Vec<u8>>| -> response::Response<Vec<u8>> {
+        // PATH_INFO is what comes after the script in the URL, which is where the API is mounted
+        let path = env::var("PATH_INFO").unwrap_or_else(|_| request.uri().path().to_string());
+
+        match &config {
+            Ok(config) => handle_request(&request, &path, config),
+            Err(e) => error_response(e),
+        }
+    });
+    Ok(())
 }
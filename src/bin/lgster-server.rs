@@ -0,0 +1,294 @@
+use clap::{App, Arg};
+use std::error::Error;
+use std::fs::{self, Permissions};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Server};
+
+use lgster::apps::AppCatalog;
+use lgster::commands::{generate_command_definitions, Command};
+use lgster::config::Config;
+use lgster::metrics::{record_tv_state, render};
+use lgster::rest::{handle_request, is_public, unauthorized_response};
+use lgster::target::Target;
+use lgster::watch::Watcher;
+
+const DEFAULT_SERVER_PORT: u16 = 8761;
+const DEFAULT_METRICS_INTERVAL: u64 = 30;
+const DEFAULT_CONCURRENCY: usize = 8;
+// the bodies are tiny JSON objects, so anything bigger than this is up to no good
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = App::new("lgster-server")
+        .version("1.0")
+        .author("Mateus \"Doodad\" Medeiros <dood.ad@outlook.com>")
+        .about(concat!(
+            "HTTP server with the same JSON API as lgster-cgi, which keeps the keys of the TVs around ",
+            "between requests instead of deriving them again every time."
+        ))
+        .arg(
+            Arg::with_name("Bind address")
+                .short("b")
+                .long("bind")
+                .value_name("127.0.0.1")
+                .help("The address to listen on (use 0.0.0.0 to be reachable from the network).")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Port")
+                .short("p")
+                .long("port")
+                .value_name("8761")
+                .help("The port to listen on.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Token")
+                .long("token")
+                .value_name("TOKEN")
+                .env("LGSTER_SERVER_TOKEN")
+                .hide_env_values(true)
+                .help(concat!(
+                    "The token every request must bring as `Authorization: Bearer <token>`. ",
+                    "Without one, anyone that can reach the server can control the TVs."
+                ))
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Unix socket")
+                .short("u")
+                .long("unix-socket")
+                .value_name("/run/lgster/lgster.sock")
+                .help(concat!(
+                    "Also listen on this Unix socket, for local clients. Requests through it don't need ",
+                    "the token, so only the user running the server may use it."
+                ))
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Concurrency")
+                .long("concurrency")
+                .value_name("8")
+                .help(concat!(
+                    "How many requests of each listener are answered at the same time. The others wait ",
+                    "for their turn."
+                ))
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Metrics interval")
+                .long("metrics-interval")
+                .value_name("SECONDS")
+                .help(concat!(
+                    "How often the state of every TV profile is polled for the gauges of /metrics ",
+                    "(30 by default, 0 to not poll at all)."
+                ))
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Config file")
+                .short("c")
+                .long("config")
+                .value_name("~/.config/lgster/config.toml")
+                .help("Path of the config file with the TV profiles and the [server] settings.")
+                .takes_value(true)
+        )
+        .get_matches();
+
+    let config = match matches.value_of("Config file").map(PathBuf::from).or_else(Config::default_path) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+
+    // the given parameters override the ones from the [server] table
+    let settings = config.server.clone().unwrap_or_default();
+    let bind_address = match matches.value_of("Bind address") {
+        Some(bind_address) => bind_address.parse::<IpAddr>()?,
+        None => settings.bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+    };
+    let port = match matches.value_of("Port") {
+        Some(port) => port.parse::<u16>()?,
+        None => settings.port.unwrap_or(DEFAULT_SERVER_PORT),
+    };
+    let token = matches.value_of("Token").map(str::to_string).or(settings.token);
+    let unix_socket = matches.value_of("Unix socket").map(PathBuf::from).or(settings.unix_socket);
+    let concurrency = match matches.value_of("Concurrency") {
+        Some(concurrency) => concurrency.parse::<usize>()?,
+        None => settings.concurrency.unwrap_or(DEFAULT_CONCURRENCY),
+    };
+    let metrics_interval = match matches.value_of("Metrics interval") {
+        Some(seconds) => seconds.parse::<u64>()?,
+        None => settings.metrics_interval.unwrap_or(DEFAULT_METRICS_INTERVAL),
+    };
+    let targets = config
+        .tvs
+        .keys()
+        .map(|name| Target::resolve(name, &config))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if token.is_none() && !bind_address.is_loopback() {
+        eprintln!("Warning: listening on {} without a token, so anyone on the network can control the TVs", bind_address);
+    }
+
+    let mut listeners = vec![];
+    let tcp_server = Server::http((bind_address, port))
+        .map_err(|e| format!("Couldn't listen on {} port {} ({})", bind_address, port, e))?;
+    eprintln!("Listening on http://{}", tcp_server.server_addr());
+    listeners.push((tcp_server, token.as_deref()));
+
+    if let Some(unix_socket) = &unix_socket {
+        let unix_server = bind_unix_socket(unix_socket)?;
+        eprintln!("Listening on {}", unix_socket.display());
+        listeners.push((unix_server, None));
+    }
+
+    let commands = generate_command_definitions();
+    // the catalog always has the queries
+    let query = commands.iter().find(|command| command.command_name == "query").unwrap();
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+
+    thread::scope(|scope| {
+        for (server, token) in &listeners {
+            let config = &config;
+            scope.spawn(move || serve(server, concurrency, *token, config));
+        }
+        if metrics_interval > 0 {
+            for target in &targets {
+                let (config, app_catalog) = (&config, &app_catalog);
+                let interval = Duration::from_secs(metrics_interval);
+                scope.spawn(move || poll_state(target, query, config, app_catalog, interval));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Listens on the Unix socket at `path`, which only the user running the server may use, since
+/// its requests don't need the token. A socket left behind by a previous run is replaced, but
+/// anything else at `path` is left alone.
+fn bind_unix_socket(path: &Path) -> Result<Server, Box<dyn Error>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(format!("{} already exists and isn't a socket", path.display()).into());
+        }
+        Ok(_) if UnixStream::connect(path).is_ok() => {
+            return Err(format!("Another server is already listening on {}", path.display()).into());
+        }
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+
+    let server = Server::http_unix(path).map_err(|e| format!("Couldn't listen on {} ({})", path.display(), e))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(server)
+}
+
+/// Keeps the gauges of a TV up to date, forever
+fn poll_state(target: &Target, query: &Command, config: &Config, app_catalog: &AppCatalog, interval: Duration) {
+    let mut watcher = Watcher::new(target, query, config, app_catalog, interval, &BTreeMap::new());
+    loop {
+        watcher.poll_due();
+        record_tv_state(&target.name, watcher.state());
+        sleep(watcher.next_poll().saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Answers the requests of a listener on `concurrency` threads, since some of them (e.g. waking
+/// a TV up and waiting for it) take a long while
+fn serve(server: &Server, concurrency: usize, token: Option<&str>, config: &Config) {
+    thread::scope(|scope| {
+        for _ in 0..concurrency.max(1) {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    respond(request, token, config);
+                }
+            });
+        }
+    });
+}
+
+fn respond(mut request: tiny_http::Request, token: Option<&str>, config: &Config) {
+    let http_request = match to_http_request(&mut request) {
+        Ok(http_request) => http_request,
+        Err(e) => {
+            eprintln!("Couldn't read a request ({})", e);
+            return;
+        }
+    };
+
+    let path = http_request.uri().path();
+    let response = if !is_public(&http_request, path) && !is_authorized(&http_request, token) {
+        unauthorized_response()
+    } else if path == "/metrics" && http_request.method() == http::Method::GET {
+        metrics_response()
+    } else {
+        handle_request(&http_request, path, config)
+    };
+
+    if let Err(e) = request.respond(to_tiny_http_response(response)) {
+        eprintln!("Couldn't answer a request ({})", e);
+    }
+}
+
+fn metrics_response() -> http::Response<Vec<u8>> {
+    http::Response::builder()
+        .header(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(render().into_bytes())
+        // the header is valid, so this can't fail
+        .unwrap()
+}
+
+fn is_authorized(request: &http::Request<Vec<u8>>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+    let given_token = request
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+
+    // compared without bailing out early, so that how long it takes says nothing about the token
+    given_token.len() == token.len()
+        && given_token
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn to_http_request(request: &mut tiny_http::Request) -> Result<http::Request<Vec<u8>>, Box<dyn Error>> {
+    let mut builder = http::Request::builder()
+        .method(request.method().as_str())
+        .uri(request.url());
+    for header in request.headers() {
+        builder = builder.header(header.field.as_str().as_str(), header.value.as_str());
+    }
+
+    let mut body = vec![];
+    request.as_reader().take(MAX_BODY_SIZE).read_to_end(&mut body)?;
+    Ok(builder.body(body)?)
+}
+
+fn to_tiny_http_response(response: http::Response<Vec<u8>>) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let (parts, body) = response.into_parts();
+    let mut tiny_http_response = tiny_http::Response::from_data(body).with_status_code(parts.status.as_u16());
+    for (name, value) in &parts.headers {
+        // tiny_http writes the length itself
+        if name == http::header::CONTENT_LENGTH {
+            continue;
+        }
+        // the names and values are already valid, since they came from an http::Response
+        tiny_http_response.add_header(Header::from_bytes(name.as_str(), value.as_bytes()).unwrap());
+    }
+    tiny_http_response
+}
@@ -1,35 +1,165 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use std::error::Error;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use lgster::comm::send_wol_packet;
+use lgster::comm::{send_wol_packet, WakeOptions};
+use lgster::config::Config;
+use lgster::wake::{
+    mac_address_for, parse_secure_on_password, wake_options_for, DEFAULT_IPV6_WAKE_TARGET,
+    DEFAULT_WAKE_TARGET,
+};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = App::new("lgster-wake")
         .version("1.0")
         .author("Mateus \"Doodad\" Medeiros <dood.ad@outlook.com>")
         .about("Super simple wol wrapper to complement lgster LG IP Control functionalities.")
+        .arg(
+            Arg::with_name("TV")
+                .value_name("TV")
+                .help(concat!(
+                    "The TV to wake up, by the name of its TV profile or by its host. Its MAC address is the one ",
+                    "cached in its profile or, failing that, the one in the ARP table. The [tv.<name>.wake] table ",
+                    "of a profile can set the defaults of all the other parameters for that TV."
+                ))
+                .required_unless("Target MAC address")
+        )
         .arg(
             Arg::with_name("Target IP address")
                 .short("t")
                 .long("target-ip")
                 .value_name("192.168.0.255")
-                .help("The target of the wake-on-lan packet. It's usually set to the broadcast of your subnet.")
+                .help(concat!(
+                    "The target of the wake-on-lan packet. It's usually set to the broadcast of your subnet ",
+                    "(255.255.255.255 by default, or ff02::1 with --ipv6)."
+                ))
                 .takes_value(true)
-                .required(true)
         )
         .arg(
             Arg::with_name("Target MAC address")
                 .short("m")
                 .long("mac-address")
                 .value_name("DO:OD:AD:15:DE:AD")
-                .help("The MAC Address of your TV.")
+                .help("The MAC Address of your TV (not needed if it's known from the TV given).")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Port")
+                .short("p")
+                .long("port")
+                .value_name("9")
+                .help("The UDP port the packet is sent to (usually 9 or 7).")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Password")
+                .long("password")
+                .value_name("00:11:22:33:44:55")
+                .help("A SecureOn password to append to the packet, for the network cards that require one.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Repeat")
+                .short("r")
+                .long("repeat")
+                .value_name("1")
+                .help("How many packets to send, since a single one can easily get lost.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Interval")
+                .long("interval")
+                .value_name("MILLISECONDS")
+                .help("How long to wait between each packet when sending more than one (100 by default).")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Interface")
+                .short("i")
+                .long("interface")
+                .value_name("eth0")
+                .help(concat!(
+                    "The network interface to send the packet from, for when the TV is on another network ",
+                    "(e.g. a VLAN). It's also the scope of link-local IPv6 targets."
+                ))
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("Source address")
+                .short("s")
+                .long("source")
+                .value_name("192.168.0.2")
+                .help("The address to send the packet from.")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("IPv6")
+                .short("6")
+                .long("ipv6")
+                .help("Send the packet to the IPv6 link-local all nodes multicast (ff02::1) of --interface.")
+        )
+        .arg(
+            Arg::with_name("Config file")
+                .short("c")
+                .long("config")
+                .value_name("~/.config/lgster/config.toml")
+                .help("Path of the config file with the TV profiles.")
                 .takes_value(true)
-                .required(true)
         )
         .get_matches();
 
-    let target_ip = matches.value_of("Target IP address").unwrap();
-    let target_mac_address = matches.value_of("Target MAC address").unwrap();
-    send_wol_packet(target_ip, target_mac_address)?;
+    let config = match matches.value_of("Config file").map(PathBuf::from).or_else(Config::default_path) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+
+    let target_mac_address = match (matches.value_of("Target MAC address"), matches.value_of("TV")) {
+        (Some(target_mac_address), _) => target_mac_address.to_string(),
+        (None, Some(tv)) => mac_address_for(tv, &config)?,
+        // this can't happen because clap requires one or the other
+        (None, None) => unreachable!(),
+    };
+
+    let (profile_target_ip, profile_options) = match matches.value_of("TV") {
+        Some(tv) => wake_options_for(tv, &config)?,
+        None => (None, WakeOptions::default()),
+    };
+    let target_ip = match matches.value_of("Target IP address") {
+        Some(target_ip) => target_ip.to_string(),
+        None if matches.is_present("IPv6") => DEFAULT_IPV6_WAKE_TARGET.to_string(),
+        None => profile_target_ip.unwrap_or_else(|| DEFAULT_WAKE_TARGET.to_string()),
+    };
+    let options = wake_options_from_matches(&matches, profile_options)?;
+
+    send_wol_packet(&target_ip, &target_mac_address, &options)?;
     Ok(())
 }
+
+/// The given parameters override the ones from the TV profile
+fn wake_options_from_matches(
+    matches: &ArgMatches,
+    mut options: WakeOptions,
+) -> Result<WakeOptions, Box<dyn Error>> {
+    if let Some(port) = matches.value_of("Port") {
+        options.port = port.parse()?;
+    }
+    if let Some(password) = matches.value_of("Password") {
+        options.password = Some(parse_secure_on_password(password)?);
+    }
+    if let Some(repeat) = matches.value_of("Repeat") {
+        options.repeat = repeat.parse()?;
+    }
+    if let Some(interval) = matches.value_of("Interval") {
+        options.interval = Duration::from_millis(interval.parse()?);
+    }
+    if let Some(interface) = matches.value_of("Interface") {
+        options.interface = Some(interface.to_string());
+    }
+    if let Some(source_address) = matches.value_of("Source address") {
+        options.source_address = Some(source_address.parse::<IpAddr>()?);
+    }
+
+    Ok(options)
+}
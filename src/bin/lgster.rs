@@ -1,57 +1,538 @@
-use std::convert::TryInto;
+use chrono::{Local, TimeDelta};
+use serde::Serialize;
 use std::error::Error;
-use std::thread::sleep;
-use std::time::Duration;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::thread::{self, sleep};
+use std::time::Instant;
 
-use lgster::cli::{generate_command_definitions, get_parameters};
-use lgster::comm::send_command;
+use lgster::actions::run_action;
+use lgster::apps::AppCatalog;
+use lgster::channel::Channel;
+use lgster::bridge::run_bridge;
+#[cfg(unix)]
+use lgster::cli::DaemonParameters;
+#[cfg(feature = "dbus")]
+use lgster::cli::DbusParameters;
+#[cfg(feature = "dbus")]
+use lgster::cli::FollowSleepParameters;
+use lgster::cli::{
+    get_parameters, DiscoverParameters, Invocation, MqttParameters, OutputFormat, Parameters,
+    ProxyParameters, ScheduleParameters, WatchParameters,
+};
+use lgster::commands::{generate_command_definitions, Command};
+use lgster::config::{MissedRuns, TvProfile};
+#[cfg(unix)]
+use lgster::daemon::{bind_socket, listener_from_systemd, run_daemon};
+#[cfg(feature = "dbus")]
+use lgster::dbus::{run_dbus, Bus, DBUS_NAME};
+#[cfg(feature = "dbus")]
+use lgster::logind::follow_sleep;
+use lgster::discover::discover;
+use lgster::fanout::run_concurrently;
+use lgster::hooks::{check_hooks, Hooks};
+use lgster::proxy::{run_proxy, ProxiedCommand};
+use lgster::schedule::{load_schedules, run_scheduler};
+use lgster::target::Target;
+use lgster::wake::{learn_mac_address, mac_address_from_reply};
+use lgster::watch::{watchable_properties, StateChange, Watcher, REACHABLE};
+
+#[derive(Serialize)]
+struct TargetReport<'a> {
+    target: &'a str,
+    host: &'a str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    responses: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    succeeded: usize,
+    failed: usize,
+    results: Vec<TargetReport<'a>>,
+}
+
+#[derive(Serialize)]
+struct ScheduleSummary<'a> {
+    name: &'a str,
+    at: &'a str,
+    tvs: Vec<&'a str>,
+    run: Vec<String>,
+    missed: MissedRuns,
+    grace_seconds: u64,
+    next_run: Option<String>,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
     let command_definitions = generate_command_definitions();
-    let params = get_parameters(&command_definitions)?;
-    let (command, action) = (params.command, params.command_action);
+    match get_parameters(&command_definitions)? {
+        Invocation::Command(params) => run_command(params),
+        Invocation::Discover(params) => run_discover(params),
+        Invocation::Mqtt(params) => run_mqtt(params, &command_definitions),
+        Invocation::Watch(params) => run_watch(params, &command_definitions),
+        Invocation::Schedule(params) => run_schedules(params, &command_definitions),
+        Invocation::Proxy(params) => run_proxy_mode(params),
+        #[cfg(unix)]
+        Invocation::Daemon(params) => run_daemon_mode(params, &command_definitions),
+        #[cfg(feature = "dbus")]
+        Invocation::Dbus(params) => run_dbus_mode(params, &command_definitions),
+        #[cfg(feature = "dbus")]
+        Invocation::FollowSleep(params) => run_follow_sleep_mode(params, &command_definitions),
+    }
+}
 
-    // TODO: better feedback to the user
-    let command_actions = command
-        .command_actions
-        .iter()
-        .find(|a| a.0 == action)
-        .expect("Invalid command");
-
-    let target_address = params.host.unwrap();
-    let salt: [u8; 16] = params.salt.0.bytes[..]
-        .try_into()
-        .expect("Invalid salt size. Should be 16 bytes.");
-    for action_to_run in command_actions.1 {
-        // TODO: test first if the command has parameters to give friendly error
-
-        let action_to_run = match &params.command_action_parameters.as_slice() {
-            [] => action_to_run.to_string(),
-            [el] => action_to_run.replace("{}", el),
-            _ => panic!("Not implemented") // TODO: think about how to generically treat multiple patterns
+fn run_command(mut params: Parameters) -> Result<(), Box<dyn Error>> {
+    let (command, action) = (params.command, params.command_action.clone());
+
+    if command.command_name == "channel" && action != "set" {
+        return edit_favourite_channels(&mut params);
+    }
+
+    if let Some(delay) = params.delay {
+        if !params.quiet {
+            let at = TimeDelta::from_std(delay)
+                .ok()
+                .and_then(|delay| Local::now().checked_add_signed(delay))
+                .ok_or("The delay of --in is too long")?;
+            eprintln!("Sending {} {} at {}", command.command_name, action, at.format("%H:%M:%S"));
+        }
+        sleep(delay);
+    }
+
+    let app_catalog = AppCatalog::with_user_apps(&params.config.apps);
+    let results = run_concurrently(&params.targets, params.concurrency, |target| {
+        // errors are turned into strings here because Box<dyn Error> can't cross threads
+        run_action(
+            command,
+            &action,
+            &params.command_action_parameters,
+            target,
+            &params.config,
+            &app_catalog,
+        )
+        .map_err(|e| e.to_string())
+    });
+
+    if let Err(e) = cache_mac_addresses(&mut params, &results) {
+        eprintln!("Couldn't cache the MAC addresses of the TVs ({})", e);
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    match params.output_format {
+        OutputFormat::Plain => print_plain_report(&params.targets, &results),
+        OutputFormat::Json => {
+            let report = Report {
+                succeeded: results.len() - failed,
+                failed,
+                results: params
+                    .targets
+                    .iter()
+                    .zip(results.iter())
+                    .map(|(target, result)| TargetReport {
+                        target: &target.name,
+                        host: &target.host,
+                        ok: result.is_ok(),
+                        responses: result.as_ref().ok().map(Vec::as_slice),
+                        error: result.as_ref().err().map(String::as_str),
+                    })
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_plain_report(targets: &[Target], results: &[Result<Vec<String>, String>]) {
+    let failed = results.iter().filter(|result| result.is_err()).count();
+
+    if let ([_], [result]) = (targets, results) {
+        match result {
+            Ok(responses) => responses.iter().for_each(|r| println!("{}", r)),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+
+    for (target, result) in targets.iter().zip(results) {
+        match result {
+            Ok(responses) => responses
+                .iter()
+                .for_each(|r| println!("{}: {}", target.name, r)),
+            Err(e) => eprintln!("{}: {}", target.name, e),
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} targets failed", failed, targets.len());
+    }
+}
+
+/// On `query mac-addresses`, saves the MAC address of every TV profile that answered into the
+/// profile, so that they can be woken up later on by name. It's taken from the ARP table, since
+/// that's the address the TV is actually using, or else from the reply. Other commands leave the
+/// config file alone, since saving it drops its comments and formatting.
+fn cache_mac_addresses(
+    params: &mut Parameters,
+    results: &[Result<Vec<String>, String>],
+) -> Result<(), Box<dyn Error>> {
+    if (params.command.command_name, params.command_action.as_str()) != ("query", "mac-addresses") {
+        return Ok(());
+    }
+
+    let mut learned_any = false;
+    for (target, result) in params.targets.iter().zip(results) {
+        let (profile, responses) = match (&target.profile, result) {
+            (Some(profile), Ok(responses)) => (profile, responses),
+            _ => continue,
         };
+        let tv_profile = params.config.tvs.get_mut(profile).unwrap();
+
+        let learned = learn_mac_address(&target.host)
+            .or_else(|| responses.iter().find_map(|r| mac_address_from_reply(r)));
+        let cached = tv_profile.mac.as_deref().map(str::to_lowercase);
+        if learned.is_some() && learned != cached {
+            tv_profile.mac = learned;
+            learned_any = true;
+        }
+    }
+
+    if learned_any {
+        if let Some(config_path) = &params.config_path {
+            params.config.save(config_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn edit_favourite_channels(params: &mut Parameters) -> Result<(), Box<dyn Error>> {
+    let profile = match params.targets.as_slice() {
+        [Target {
+            profile: Some(profile),
+            ..
+        }] => profile.clone(),
+        _ => {
+            return Err(
+                "Favourite channels are stored per TV profile, so the target must be a single one".into(),
+            )
+        }
+    };
+    let channels = &mut params.config.tvs.get_mut(&profile).unwrap().channels;
 
-        let response = match send_command(
-            &target_address,
-            params.port,
-            &params.keycode,
-            &salt,
-            action_to_run,
-        ) {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("{}", e);
-                return Ok(());
+    match (params.command_action.as_str(), params.command_action_parameters.as_slice()) {
+        ("favourites", []) => {
+            for (name, channel) in channels.iter() {
+                println!("{} = {}", name, channel);
             }
-        };
-        println!(
-            "{}",
-            response
-                .chars()
-                .take_while(|c| *c != '\n')
-                .collect::<String>()
+            return Ok(());
+        }
+        ("favourite", [name, channel]) => {
+            let channel: Channel = channel.parse()?;
+            channels.insert(name.to_string(), channel.to_string());
+        }
+        ("unfavourite", [name]) => {
+            channels
+                .remove(name)
+                .ok_or_else(|| format!("{} is not a favourite channel of {}", name, profile))?;
+        }
+        _ => {
+            return Err(concat!(
+                "Usage: channel favourites | channel favourite <name> <channel> | ",
+                "channel unfavourite <name>"
+            )
+            .into())
+        }
+    }
+
+    let config_path = params
+        .config_path
+        .as_ref()
+        .ok_or("Couldn't find where to save the config file (use --config)")?;
+    params.config.save(config_path)?;
+    Ok(())
+}
+
+fn run_discover(mut params: DiscoverParameters) -> Result<(), Box<dyn Error>> {
+    let tvs = discover(&params.options)?;
+    let profiles: Vec<Option<String>> = tvs
+        .iter()
+        .map(|tv| {
+            params
+                .config
+                .tvs
+                .iter()
+                .find(|(_, tv_profile)| tv_profile.host == tv.ip.to_string())
+                .map(|(name, _)| name.clone())
+        })
+        .collect();
+
+    match params.output_format {
+        OutputFormat::Plain => {
+            if tvs.is_empty() {
+                eprintln!("No TVs found");
+            }
+            for (tv, profile) in tvs.iter().zip(profiles.iter()) {
+                println!(
+                    "{:<15}  {:<24}  {:<16}  {:<17}  ip-control: {:<3}  profile: {}",
+                    tv.ip,
+                    tv.friendly_name.as_deref().unwrap_or("-"),
+                    tv.model_name.as_deref().unwrap_or("-"),
+                    tv.mac_address.as_deref().unwrap_or("-"),
+                    if tv.ip_control { "on" } else { "off" },
+                    profile.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&tvs)?),
+    }
+
+    if !params.write_profiles {
+        return Ok(());
+    }
+
+    let mut wrote_any_profile = false;
+    let stdin = io::stdin();
+    let ask = |question: String| -> io::Result<String> {
+        eprint!("{}", question);
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        stdin.lock().read_line(&mut answer)?;
+        Ok(answer.trim().to_string())
+    };
+    for (tv, _) in tvs.iter().zip(profiles.iter()).filter(|(_, profile)| profile.is_none()) {
+        let name = ask(format!(
+            "Name of the TV profile for {} ({}), or empty to skip: ",
+            tv.ip,
+            tv.friendly_name.as_deref().unwrap_or("unknown name")
+        ))?;
+        if name.is_empty() {
+            continue;
+        }
+        let keycode = ask("Its keycode (shown on the TV when enabling IP Control), or empty to set it later: ".to_string())?;
+
+        params.config.tvs.insert(
+            name,
+            TvProfile {
+                host: tv.ip.to_string(),
+                keycode: Some(keycode).filter(|keycode| !keycode.is_empty()),
+                mac: tv.mac_address.clone(),
+                ..TvProfile::default()
+            },
         );
-        sleep(Duration::from_millis(200));
+        wrote_any_profile = true;
+    }
+
+    if wrote_any_profile {
+        let config_path = params
+            .config_path
+            .as_ref()
+            .ok_or("Couldn't find where to save the config file (use --config)")?;
+        params.config.save(config_path)?;
+    }
+    Ok(())
+}
+
+fn run_mqtt(params: MqttParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    if params.targets.is_empty() {
+        return Err("There are no TVs to bridge (use --target-host, --group or TV profiles)".into());
     }
+    eprintln!(
+        "Bridging {} TVs to the MQTT broker at {}:{}",
+        params.targets.len(),
+        params.options.mqtt.host,
+        params.options.mqtt.port
+    );
+    run_bridge(&params.options, &params.targets, &params.config, commands)
+}
+
+fn run_watch(params: WatchParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    // the catalog always has the queries
+    let query = commands.iter().find(|command| command.command_name == "query").unwrap();
+    let properties = watchable_properties(query);
+    if let Some(unknown) = params.intervals.keys().find(|property| !properties.contains(&property.as_str())) {
+        return Err(format!(
+            "There's no property {} to watch (they are {})",
+            unknown,
+            properties.join(", ")
+        )
+        .into());
+    }
+    if let Some(hook) = params
+        .config
+        .hooks
+        .iter()
+        .find(|hook| hook.property != REACHABLE && !properties.contains(&hook.property.as_str()))
+    {
+        return Err(format!("There's no property {} for a hook to be about", hook.property).into());
+    }
+    check_hooks(&params.config.hooks)?;
+    if params.targets.is_empty() {
+        return Err("There are no TVs to watch (use --target-host, --group or TV profiles)".into());
+    }
+
+    let app_catalog = AppCatalog::with_user_apps(&params.config.apps);
+    thread::scope(|scope| {
+        for target in &params.targets {
+            let (params, app_catalog) = (&params, &app_catalog);
+            scope.spawn(move || {
+                let mut watcher = Watcher::new(
+                    target,
+                    query,
+                    &params.config,
+                    app_catalog,
+                    params.interval,
+                    &params.intervals,
+                );
+                let mut hooks = Hooks::new(&params.config.hooks, target);
+                loop {
+                    for change in watcher.poll_due() {
+                        print_change(&change, &params.output_format);
+                        hooks.handle(&change);
+                    }
+                    hooks.run_due();
+
+                    let next_wake_up = match hooks.next_due() {
+                        Some(next_due) => next_due.min(watcher.next_poll()),
+                        None => watcher.next_poll(),
+                    };
+                    sleep(next_wake_up.saturating_duration_since(Instant::now()));
+                }
+            });
+        }
+    });
     Ok(())
 }
+
+fn print_change(change: &StateChange, output_format: &OutputFormat) {
+    match (output_format, &change.old) {
+        (OutputFormat::Plain, Some(old)) => {
+            println!("{} {}: {} -> {}", change.tv, change.property, old, change.new)
+        }
+        (OutputFormat::Plain, None) => println!("{} {}: {}", change.tv, change.property, change.new),
+        // serializing this plain struct can't fail
+        (OutputFormat::Json, _) => println!("{}", serde_json::to_string(change).unwrap()),
+    }
+}
+
+fn run_schedules(params: ScheduleParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    let schedules = load_schedules(&params.config, commands)?;
+    if !params.list {
+        if schedules.is_empty() {
+            return Err("There are no [[schedule]]s in the config file (see lgster schedule --help)".into());
+        }
+        run_scheduler(&schedules, &params.config, params.concurrency);
+    }
+
+    let now = Local::now();
+    match params.output_format {
+        OutputFormat::Plain => {
+            for schedule in &schedules {
+                let tvs: Vec<&str> = schedule.targets.iter().map(|target| target.name.as_str()).collect();
+                let steps: Vec<String> = schedule.steps.iter().map(|step| step.to_string()).collect();
+                let next_run = match schedule.rule.next_after(now) {
+                    Some(next_run) => next_run.format("%a %Y-%m-%d %H:%M").to_string(),
+                    None => "never".to_string(),
+                };
+                println!("{} ({}), next on {}", schedule.name, schedule.at, next_run);
+                println!("  on {}: {}", tvs.join(", "), steps.join(", "));
+                if schedule.missed == MissedRuns::CatchUp {
+                    println!("  catching up on runs missed by up to {} minutes", schedule.grace.as_secs() / 60);
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let summaries: Vec<ScheduleSummary> = schedules
+                .iter()
+                .map(|schedule| ScheduleSummary {
+                    name: &schedule.name,
+                    at: &schedule.at,
+                    tvs: schedule.targets.iter().map(|target| target.name.as_str()).collect(),
+                    run: schedule.steps.iter().map(|step| step.to_string()).collect(),
+                    missed: schedule.missed,
+                    grace_seconds: schedule.grace.as_secs(),
+                    next_run: schedule.rule.next_after(now).map(|next_run| next_run.to_rfc3339()),
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&summaries)?);
+        }
+    }
+    Ok(())
+}
+
+fn run_proxy_mode(params: ProxyParameters) -> Result<(), Box<dyn Error>> {
+    eprintln!(
+        "Passing the commands sent to {} on to {}:{}",
+        params.options.listen, params.upstream.host, params.upstream.port
+    );
+    run_proxy(&params.options, &params.upstream, |proxied_command| {
+        print_proxied_command(proxied_command, &params.output_format)
+    })
+}
+
+#[cfg(unix)]
+fn run_daemon_mode(params: DaemonParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    let (listener, socket) = match listener_from_systemd() {
+        Some(listener) => (listener, "the socket passed by systemd".to_string()),
+        None => (bind_socket(&params.socket)?, params.socket.display().to_string()),
+    };
+    eprintln!("Listening for lgster commands at {}", socket);
+    run_daemon(listener, &params.options, commands)
+}
+
+#[cfg(feature = "dbus")]
+fn run_dbus_mode(params: DbusParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    if params.targets.is_empty() {
+        return Err("There are no TVs to serve (use --target-host, --group or TV profiles)".into());
+    }
+    eprintln!(
+        "Serving {} TVs on the {} bus as {}",
+        params.targets.len(),
+        match params.options.bus {
+            Bus::Session => "session",
+            Bus::System => "system",
+        },
+        DBUS_NAME
+    );
+    run_dbus(&params.options, &params.targets, params.config, commands)
+}
+
+#[cfg(feature = "dbus")]
+fn run_follow_sleep_mode(params: FollowSleepParameters, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    if params.targets.is_empty() {
+        return Err("There are no TVs to follow this computer (use --target-host, --group or TV profiles)".into());
+    }
+    eprintln!("Turning {} TVs off and on with this computer", params.targets.len());
+    follow_sleep(&params.options, &params.targets, &params.config, commands)
+}
+
+fn print_proxied_command(proxied_command: &ProxiedCommand, output_format: &OutputFormat) {
+    if let OutputFormat::Json = output_format {
+        // serializing this plain struct can't fail
+        println!("{}", serde_json::to_string(proxied_command).unwrap());
+        return;
+    }
+
+    let mut line = format!(
+        "{} {}",
+        proxied_command.client,
+        proxied_command.command.as_deref().unwrap_or("(couldn't decrypt it)")
+    );
+    if let Some(rewritten) = &proxied_command.rewritten {
+        line.push_str(&format!(" (rewritten to {})", rewritten));
+    }
+    if proxied_command.blocked {
+        line.push_str(" (blocked)");
+    }
+    match (&proxied_command.reply, &proxied_command.error) {
+        (_, Some(error)) => line.push_str(&format!(" -> failed: {}", error)),
+        (Some(reply), None) => line.push_str(&format!(" -> {}", reply)),
+        (None, None) => line.push_str(" -> no reply"),
+    }
+    println!("{} ({:.1}ms)", line, proxied_command.took_ms);
+}
@@ -0,0 +1,312 @@
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use crate::actions::{run_action, UsageError};
+use crate::apps::AppCatalog;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::error::ErrorClass;
+use crate::fanout::run_concurrently;
+use crate::mqtt::{Message, MqttClient, MqttOptions};
+use crate::target::Target;
+use crate::watch::{Watcher, REACHABLE};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct BridgeOptions {
+    pub mqtt: MqttOptions,
+    /// The first level of every topic (e.g. the `lgster` of `lgster/<tv>/set/volume`)
+    pub prefix: String,
+    /// Where the Home Assistant discovery payloads are published, if at all
+    pub discovery_prefix: Option<String>,
+    /// How often the state of the TVs is polled
+    pub interval: Duration,
+    pub concurrency: usize,
+}
+
+/// Bridges the TVs to an MQTT broker, forever (reconnecting whenever the connection is lost):
+/// - `<prefix>/<tv>/<command>/<action>` runs an action, with the payload as its parameter
+///   (e.g. `lgster/lobby/set/volume` with `10`)
+/// - `<prefix>/<tv>/<command>` runs the action in the payload (e.g. `lgster/lobby/power` with `off`)
+/// - `<prefix>/<tv>/result` gets the result of each of them as JSON
+/// - `<prefix>/<tv>/state/<power|volume|mute|current_app>` get the polled state (retained)
+/// - `<prefix>/status` tells whether the bridge is `online` or `offline`
+pub fn run_bridge(options: &BridgeOptions, targets: &[Target], config: &Config, commands: &[Command]) -> ! {
+    let mut mqtt_options = options.mqtt.clone();
+    mqtt_options.will = Some((status_topic(options), "offline".to_string()));
+
+    loop {
+        let result = MqttClient::connect(&mqtt_options)
+            .and_then(|client| run_session(&client, options, targets, config, commands));
+        if let Err(e) = result {
+            eprintln!(
+                "Lost the connection to the MQTT broker at {}:{} ({}), reconnecting in {}s",
+                mqtt_options.host,
+                mqtt_options.port,
+                e,
+                RECONNECT_DELAY.as_secs()
+            );
+        }
+        sleep(RECONNECT_DELAY);
+    }
+}
+
+fn run_session(
+    client: &MqttClient,
+    options: &BridgeOptions,
+    targets: &[Target],
+    config: &Config,
+    commands: &[Command],
+) -> io::Result<()> {
+    client.publish(&status_topic(options), b"online", true)?;
+    if let Some(discovery_prefix) = &options.discovery_prefix {
+        for target in targets {
+            for (topic, payload) in discovery_payloads(options, discovery_prefix, target, commands) {
+                client.publish(&topic, payload.to_string().as_bytes(), true)?;
+            }
+        }
+    }
+
+    let mut command_topics = vec![];
+    for target in targets {
+        for command in commands {
+            let topic = format!("{}/{}/{}", options.prefix, target.name, command.command_name);
+            command_topics.push(format!("{}/+", topic));
+            command_topics.push(topic);
+        }
+    }
+    client.subscribe(&command_topics)?;
+
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+    let stop = AtomicBool::new(false);
+    let poll_now = AtomicBool::new(true);
+
+    thread::scope(|scope| {
+        let poller = scope.spawn(|| {
+            let result = poll_states(client, options, targets, config, commands, &app_catalog, &stop, &poll_now);
+            // so that the loop below notices it too
+            client.disconnect();
+            result
+        });
+
+        let result = loop {
+            let message = match client.next_message() {
+                Ok(message) => message,
+                Err(e) => break Err(e),
+            };
+            if let Some((tv, result)) = run_command(&message, options, targets, config, commands, &app_catalog) {
+                let topic = format!("{}/{}/result", options.prefix, tv);
+                if let Err(e) = client.publish(&topic, result.to_string().as_bytes(), false) {
+                    break Err(e);
+                }
+                poll_now.store(true, Ordering::Relaxed);
+            }
+        };
+
+        stop.store(true, Ordering::Relaxed);
+        poller.join().unwrap().and(result)
+    })
+}
+
+/// Runs the command of a message, returning the TV it was for and the result to publish
+fn run_command(
+    message: &Message,
+    options: &BridgeOptions,
+    targets: &[Target],
+    config: &Config,
+    commands: &[Command],
+    app_catalog: &AppCatalog,
+) -> Option<(String, serde_json::Value)> {
+    let payload = String::from_utf8_lossy(&message.payload).trim().to_string();
+    let topic = message.topic.strip_prefix(&format!("{}/", options.prefix))?;
+
+    let (tv, command_name, action, parameters) = match topic.split('/').collect::<Vec<_>>().as_slice() {
+        [tv, command_name] => (*tv, *command_name, payload, vec![]),
+        [tv, command_name, action] if payload.is_empty() => (*tv, *command_name, action.to_string(), vec![]),
+        [tv, command_name, action] => (*tv, *command_name, action.to_string(), vec![parameter_from_payload(&payload)]),
+        _ => return None,
+    };
+    let target = targets.iter().find(|target| target.name == tv)?;
+    let command = commands.iter().find(|command| command.command_name == command_name)?;
+
+    let result = if command.command_name == "channel" && action != "set" {
+        // favourite channels are only changed from the command line
+        Err(Box::new(UsageError::new("channel set <channel or favourite>")) as Box<dyn Error>)
+    } else {
+        run_action(command, &action, &parameters, target, config, app_catalog)
+    };
+
+    let result = match result {
+        Ok(responses) => json!({
+            "command": command_name,
+            "action": action,
+            "ok": true,
+            "responses": responses,
+        }),
+        Err(e) => json!({
+            "command": command_name,
+            "action": action,
+            "ok": false,
+            "error": e.to_string(),
+            "class": ErrorClass::of(e.as_ref()),
+        }),
+    };
+    Some((tv.to_string(), result))
+}
+
+/// Home Assistant sends numbers as floats (e.g. `10.0`), which the TVs don't understand
+fn parameter_from_payload(payload: &str) -> String {
+    match payload.parse::<f64>() {
+        Ok(number) if number.fract() == 0.0 => format!("{}", number as i64),
+        _ => payload.to_string(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn poll_states(
+    client: &MqttClient,
+    options: &BridgeOptions,
+    targets: &[Target],
+    config: &Config,
+    commands: &[Command],
+    app_catalog: &AppCatalog,
+    stop: &AtomicBool,
+    poll_now: &AtomicBool,
+) -> io::Result<()> {
+    // the catalog always has the queries
+    let query = commands.iter().find(|command| command.command_name == "query").unwrap();
+    let watchers: Vec<Mutex<Watcher>> = targets
+        .iter()
+        .map(|target| Mutex::new(Watcher::new(target, query, config, app_catalog, options.interval, &BTreeMap::new())))
+        .collect();
+    // only the changes are published, but everything is published again on every new connection
+    let mut published: HashMap<String, String> = HashMap::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        if poll_now.swap(false, Ordering::Relaxed) {
+            watchers.iter().for_each(|watcher| watcher.lock().unwrap().poll_everything_now());
+        }
+
+        let now = Instant::now();
+        if watchers.iter().any(|watcher| watcher.lock().unwrap().next_poll() <= now) {
+            run_concurrently(&watchers, options.concurrency, |watcher| {
+                watcher.lock().unwrap().poll_due();
+            });
+            for (target, watcher) in targets.iter().zip(&watchers) {
+                for (property, value) in watcher.lock().unwrap().state() {
+                    let (property, value) = published_state(property, value);
+                    let topic = format!("{}/{}/state/{}", options.prefix, target.name, property);
+                    if published.get(&topic) != Some(&value) {
+                        client.publish(&topic, value.as_bytes(), true)?;
+                        published.insert(topic, value);
+                    }
+                }
+            }
+        }
+
+        client.keep_alive()?;
+        sleep(Duration::from_millis(250));
+    }
+    Ok(())
+}
+
+/// A watched property as it's published, where whether the TV answers is its `power`
+fn published_state(property: &str, value: &str) -> (String, String) {
+    match (property, value) {
+        (REACHABLE, "true") => ("power".to_string(), "on".to_string()),
+        (REACHABLE, _) => ("power".to_string(), "off".to_string()),
+        _ => (property.replace('-', "_"), value.to_string()),
+    }
+}
+
+fn status_topic(options: &BridgeOptions) -> String {
+    format!("{}/status", options.prefix)
+}
+
+/// The Home Assistant entities of a TV, all of them under a device of its own. There's no MQTT
+/// media_player in Home Assistant, so the TV is made of a switch for the power, a number for
+/// the volume, a switch for the mute, a sensor for the current app and a select for the input.
+fn discovery_payloads(
+    options: &BridgeOptions,
+    discovery_prefix: &str,
+    target: &Target,
+    commands: &[Command],
+) -> Vec<(String, serde_json::Value)> {
+    let object_id: String = target
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let tv_topic = format!("{}/{}", options.prefix, target.name);
+    let inputs: Vec<&str> = commands
+        .iter()
+        .filter(|command| command.command_name == "input")
+        .flat_map(|command| command.command_actions.iter().map(|(action, _)| *action))
+        .collect();
+
+    let entities = vec![
+        ("switch", "power", json!({
+            "name": "Power",
+            "command_topic": format!("{}/power", tv_topic),
+            "state_topic": format!("{}/state/power", tv_topic),
+            "payload_on": "on",
+            "payload_off": "off",
+            "state_on": "on",
+            "state_off": "off",
+            "icon": "mdi:television",
+        })),
+        ("number", "volume", json!({
+            "name": "Volume",
+            "command_topic": format!("{}/set/volume", tv_topic),
+            "state_topic": format!("{}/state/volume", tv_topic),
+            "min": 0,
+            "max": 100,
+            "step": 1,
+            "icon": "mdi:volume-high",
+        })),
+        ("switch", "mute", json!({
+            "name": "Mute",
+            "command_topic": format!("{}/set/mute", tv_topic),
+            "state_topic": format!("{}/state/mute", tv_topic),
+            "payload_on": "on",
+            "payload_off": "off",
+            "state_on": "on",
+            "state_off": "off",
+            "icon": "mdi:volume-off",
+        })),
+        ("sensor", "current_app", json!({
+            "name": "Current app",
+            "state_topic": format!("{}/state/current_app", tv_topic),
+            "icon": "mdi:application",
+        })),
+        ("select", "input", json!({
+            "name": "Input",
+            "command_topic": format!("{}/input", tv_topic),
+            "options": inputs,
+            "optimistic": true,
+            "icon": "mdi:video-input-hdmi",
+        })),
+    ];
+
+    entities
+        .into_iter()
+        .map(|(component, entity, mut payload)| {
+            payload["unique_id"] = json!(format!("lgster_{}_{}", object_id, entity));
+            payload["availability_topic"] = json!(status_topic(options));
+            payload["device"] = json!({
+                "identifiers": [format!("lgster_{}", object_id)],
+                "name": target.name,
+                "manufacturer": "LG",
+            });
+            let topic = format!("{}/{}/lgster_{}/{}/config", discovery_prefix, component, object_id, entity);
+            (topic, payload)
+        })
+        .collect()
+}
@@ -0,0 +1,106 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct InvalidByteSequenceError {
+    invalid_byte: String,
+}
+
+impl fmt::Display for InvalidByteSequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid byte {} (should be a hex number from 00-FF)",
+            self.invalid_byte
+        )
+    }
+}
+
+impl Error for InvalidByteSequenceError {}
+
+#[derive(Debug)]
+pub struct WrongByteSequenceSize {
+    should_be_size: usize,
+}
+
+impl fmt::Display for WrongByteSequenceSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Wrong size of byte sequence (should be {}))",
+            self.should_be_size
+        )
+    }
+}
+
+impl Error for WrongByteSequenceSize {}
+
+/// Exactly `N` hex bytes separated by `delimiter` (e.g. `a8:23:fe:01:02:03`)
+pub fn parse_byte_sequence<const N: usize>(sequence: &str, delimiter: char) -> Result<[u8; N], Box<dyn Error>> {
+    let mut collected_bytes = Vec::with_capacity(N);
+    for (i, byte) in sequence.split(delimiter).take(N + 1).enumerate() {
+        if i == N {
+            return Err(Box::new(WrongByteSequenceSize { should_be_size: N }));
+        }
+        match u8::from_str_radix(byte, 16) {
+            Ok(byte) => collected_bytes.push(byte),
+            Err(_) => {
+                return Err(Box::new(InvalidByteSequenceError {
+                    invalid_byte: byte.to_string(),
+                }))
+            }
+        }
+    }
+
+    collected_bytes[..]
+        .try_into()
+        .map_err(|_| WrongByteSequenceSize { should_be_size: N }.into())
+}
+
+/// A MAC address (or a SecureOn password, which is written the same way), like `a8:23:fe:01:02:03`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl FromStr for MacAddress {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MacAddress(parse_byte_sequence(s, ':')?))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_byte_sequence(f, &self.0, ':')
+    }
+}
+
+/// The salt the key is derived with, like `63-61-b8-0e-9b-dc-a6-63-8d-07-20-f2-cc-56-8f-b9`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Salt(pub [u8; 16]);
+
+impl FromStr for Salt {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Salt(parse_byte_sequence(s, '-')?))
+    }
+}
+
+impl fmt::Display for Salt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_byte_sequence(f, &self.0, '-')
+    }
+}
+
+fn write_byte_sequence(f: &mut fmt::Formatter, bytes: &[u8], delimiter: char) -> fmt::Result {
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", delimiter)?;
+        }
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct InvalidChannelError {
+    channel: String,
+}
+
+impl fmt::Display for InvalidChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid channel {} (should be a number, optionally followed by a sub-channel, e.g. 7 or 7-1)",
+            self.channel
+        )
+    }
+}
+
+impl Error for InvalidChannelError {}
+
+/// A broadcast channel, with an optional sub-channel (e.g. `7-1` for ATSC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channel {
+    pub major: u32,
+    pub minor: Option<u32>,
+}
+
+impl Channel {
+    /// The command that tunes directly to this channel on TVs that support it
+    pub fn set_command(&self) -> String {
+        format!("CHANNEL_SET {}", self)
+    }
+
+    /// The key presses that tune to this channel on any TV, as if typed on the remote
+    pub fn key_sequence(&self) -> Vec<String> {
+        let mut keys = digit_keys(self.major);
+        if let Some(minor) = self.minor {
+            keys.push("KEY_ACTION dash".to_string());
+            keys.extend(digit_keys(minor));
+        }
+        keys.push("KEY_ACTION ok".to_string());
+        keys
+    }
+}
+
+fn digit_keys(number: u32) -> Vec<String> {
+    number
+        .to_string()
+        .chars()
+        .map(|digit| format!("KEY_ACTION number{}", digit))
+        .collect()
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.minor {
+            Some(minor) => write!(f, "{}-{}", self.major, minor),
+            None => write!(f, "{}", self.major),
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = InvalidChannelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidChannelError {
+            channel: s.to_string(),
+        };
+        let parse_number = |n: &str| {
+            if n.chars().all(|c| c.is_ascii_digit()) {
+                n.parse::<u32>().map_err(|_| invalid())
+            } else {
+                Err(invalid())
+            }
+        };
+
+        let mut parts = s.trim().splitn(2, &['-', '.'][..]);
+        let major = parse_number(parts.next().unwrap_or(""))?;
+        let minor = match parts.next() {
+            Some(minor) => Some(parse_number(minor)?),
+            None => None,
+        };
+
+        Ok(Channel { major, minor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(major: u32, minor: Option<u32>) -> Channel {
+        Channel { major, minor }
+    }
+
+    #[test]
+    fn parses_channels() {
+        assert_eq!("7".parse::<Channel>().unwrap(), channel(7, None));
+        assert_eq!("7-1".parse::<Channel>().unwrap(), channel(7, Some(1)));
+        assert_eq!("7.1".parse::<Channel>().unwrap(), channel(7, Some(1)));
+        assert_eq!(" 105-12 ".parse::<Channel>().unwrap(), channel(105, Some(12)));
+        assert_eq!("007".parse::<Channel>().unwrap(), channel(7, None));
+    }
+
+    #[test]
+    fn rejects_invalid_channels() {
+        for invalid in ["", "-", "7-", "-1", "7-1-2", "7--1", "+7", "7-+1", "news", "7a", "99999999999"] {
+            assert!(invalid.parse::<Channel>().is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn writes_channels_like_they_are_parsed() {
+        assert_eq!(channel(7, None).to_string(), "7");
+        assert_eq!(channel(7, Some(1)).to_string(), "7-1");
+        assert_eq!(channel(7, Some(1)).set_command(), "CHANNEL_SET 7-1");
+    }
+
+    #[test]
+    fn types_channels_on_the_remote() {
+        assert_eq!(channel(7, None).key_sequence(), ["KEY_ACTION number7", "KEY_ACTION ok"]);
+        assert_eq!(
+            channel(10, Some(2)).key_sequence(),
+            [
+                "KEY_ACTION number1",
+                "KEY_ACTION number0",
+                "KEY_ACTION dash",
+                "KEY_ACTION number2",
+                "KEY_ACTION ok",
+            ]
+        );
+    }
+}
@@ -1,12 +1,23 @@
 mod commands;
-mod convert;
+mod modes;
 mod parameters;
 
 pub use self::commands::generate_clap_subcommands;
-pub use self::commands::generate_command_definitions;
 
-pub use self::convert::FixedSizeByteSequenceParameter;
+#[cfg(unix)]
+pub use self::modes::DaemonParameters;
+#[cfg(feature = "dbus")]
+pub use self::modes::DbusParameters;
+pub use self::modes::DiscoverParameters;
+#[cfg(feature = "dbus")]
+pub use self::modes::FollowSleepParameters;
+pub use self::modes::MqttParameters;
+pub use self::modes::ProxyParameters;
+pub use self::modes::ScheduleParameters;
+pub use self::modes::WatchParameters;
 
 pub use self::parameters::get_parameters;
+pub use self::parameters::Invocation;
+pub use self::parameters::OutputFormat;
 pub use self::parameters::Parameters;
 pub use self::parameters::ParseParameterError;
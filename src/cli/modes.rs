@@ -0,0 +1,679 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::collections::BTreeMap;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use super::parameters::{
+    apply_target_parameters, concurrency_from_matches, config_from_matches, output_format_from_matches,
+    parse_seconds, port_from_matches, targets_from_matches, OutputFormat, ParseParameterError,
+};
+use crate::bridge::BridgeOptions;
+use crate::config::Config;
+#[cfg(unix)]
+use crate::daemon::{socket_path, DaemonOptions};
+#[cfg(feature = "dbus")]
+use crate::dbus::{Bus, DbusOptions};
+#[cfg(feature = "dbus")]
+use crate::logind::SleepOptions;
+use crate::discover::{parse_ipv4_cidr, DiscoverOptions};
+use crate::mqtt::{MqttOptions, DEFAULT_MQTT_PORT};
+use crate::proxy::{ProxyOptions, ProxyRule};
+use crate::target::Target;
+
+/// The subcommands that aren't generated from the command definitions,
+/// because they aren't a simple command sent to a TV
+pub fn generate_mode_subcommands<'a>() -> Vec<App<'a, 'a>> {
+    let mut subcommands = vec![SubCommand::with_name("discover")
+        .about("Find LG TVs on the local network")
+        .long_about(concat!(
+            "Find LG TVs on the local network", "\n",
+            "\n",
+            "By default it looks for them with SSDP (UPnP), which is how most things find TVs.", "\n",
+            "TVs that don't answer to it can still be found by probing the IP Control port of every", "\n",
+            "address of a range with --scan. For each TV found, its IP address, name, model and MAC", "\n",
+            "address (from the ARP table) are reported, when available."
+        ))
+        .arg(
+            Arg::with_name("Scan range")
+                .long("scan")
+                .value_name("192.168.0.0/24")
+                .help("Also probe the IP Control port of every address in this range (can be given more than once)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("No SSDP")
+                .long("no-ssdp")
+                .help("Don't look for TVs with SSDP (only makes sense with --scan)"),
+        )
+        .arg(
+            Arg::with_name("Timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("How long to wait for answers")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("Write profiles")
+                .long("write-profiles")
+                .help("Offer to write a TV profile to the config file for each TV found"),
+        ),
+    SubCommand::with_name("mqtt")
+        .about("Bridge the TVs to an MQTT broker, with Home Assistant discovery")
+        .long_about(concat!(
+            "Bridge the TVs to an MQTT broker, with Home Assistant discovery", "\n",
+            "\n",
+            "Every command can be sent to <prefix>/<tv>/<command>/<action>, with the parameter as the payload", "\n",
+            "(e.g. lgster/lobby/set/volume with 10), or to <prefix>/<tv>/<command> with the action as the", "\n",
+            "payload (e.g. lgster/lobby/power with off). The result of each one is published to", "\n",
+            "<prefix>/<tv>/result. The power (whether the TV answers), volume, mute and current app of each", "\n",
+            "TV are polled and published to <prefix>/<tv>/state/<name> as retained messages.", "\n",
+            "\n",
+            "It bridges the TVs given by --target-host and --group or, if none, every TV profile. The", "\n",
+            "[mqtt] table of the config file can have the same settings as the parameters below."
+        ))
+        .arg(
+            Arg::with_name("Broker")
+                .long("broker")
+                .value_name("localhost:1883")
+                .help("The host of the MQTT broker, optionally with the port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("Username")
+                .long("username")
+                .value_name("USERNAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("Password")
+                .long("password")
+                .value_name("PASSWORD")
+                .env("LGSTER_MQTT_PASSWORD")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("Prefix")
+                .long("prefix")
+                .value_name("lgster")
+                .help("The first level of every topic")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("Discovery prefix")
+                .long("discovery-prefix")
+                .value_name("homeassistant")
+                .help("Where the Home Assistant discovery payloads are published")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("No discovery")
+                .long("no-discovery")
+                .help("Don't publish the Home Assistant discovery payloads"),
+        )
+        .arg(
+            Arg::with_name("Interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("How often the state of the TVs is polled (10 by default)")
+                .takes_value(true),
+        ),
+    SubCommand::with_name("watch")
+        .about("Watch the state of the TVs and report whenever it changes")
+        .long_about(concat!(
+            "Watch the state of the TVs and report whenever it changes", "\n",
+            "\n",
+            "The properties are the `query` actions about the current state of the TV (e.g. volume, mute and", "\n",
+            "current-app), plus `reachable`, which is whether the TV answers at all. Each one is polled at its", "\n",
+            "own interval and reported (one line or JSON object per change) only when its value changes. The", "\n",
+            "first value of each property is reported too, as a change from nothing.", "\n",
+            "\n",
+            "The screen state (see `lgster screen`) can't be watched, since the TVs have no query for it.", "\n",
+            "\n",
+            "It watches the TVs given by --target-host and --group or, if none, every TV profile, and runs", "\n",
+            "the [[hook]]s of the config file for the changes (but not for the first values)."
+        ))
+        .arg(
+            Arg::with_name("Interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("How often each property is polled")
+                .takes_value(true)
+                .default_value("5"),
+        )
+        .arg(
+            Arg::with_name("Property interval")
+                .long("every")
+                .value_name("PROPERTY=SECONDS")
+                .help("How often a specific property is polled (e.g. current-app=1), with 0 to not watch it at all")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        ),
+    SubCommand::with_name("schedule")
+        .about("Run the schedules of the config file, e.g. to turn the TVs off every night")
+        .long_about(concat!(
+            "Run the schedules of the config file, e.g. to turn the TVs off every night", "\n",
+            "\n",
+            "Each [[schedule]] table runs some commands on some TVs at some times (in local time), e.g.:", "\n",
+            "  [[schedule]]", "\n",
+            "  name = \"morning\"", "\n",
+            "  at = \"weekdays 08:00\"", "\n",
+            "  tvs = [\"lobby\"] # TV profiles, hosts or groups (every TV profile by default)", "\n",
+            "  run = [\"power on --wait\", \"input hdmi-1\"]", "\n",
+            "\n",
+            "The times are like `22:30` (every day), `weekdays 08:00`, `weekends 10:00`, `mon,wed,fri 12:00,18:30`", "\n",
+            "or `mon-thu 19:00`. A run that was missed (because lgster wasn't running or the machine was asleep)", "\n",
+            "is skipped, unless the schedule has `missed = \"catch-up\"`, in which case it's run as soon as", "\n",
+            "possible if it's not later than its `grace` (e.g. \"2h\", 1 hour by default).", "\n",
+            "\n",
+            "It runs until it's stopped. For a single delayed command, every command takes --in instead", "\n",
+            "(e.g. lgster -t lobby power off --in 45m)."
+        ))
+        .subcommand(SubCommand::with_name("list").about("List the schedules and when each one runs next")),
+    SubCommand::with_name("proxy")
+        .about("Sit between another IP Control client and a TV, showing their commands and replies decrypted")
+        .long_about(concat!(
+            "Sit between another IP Control client and a TV, showing their commands and replies decrypted", "\n",
+            "\n",
+            "Point the client (e.g. a control panel, an app or another script) at the proxy instead of the TV.", "\n",
+            "Every command it sends is passed on to the TV and the reply back to it, while both are shown", "\n",
+            "decrypted with the keycode of the TV (--keycode or its TV profile), one line or JSON object per", "\n",
+            "command. Commands that can't be decrypted (e.g. because of another keycode) are passed on as they are.", "\n",
+            "\n",
+            "Commands can also be rewritten or blocked by rules, where a pattern is either a whole command or,", "\n",
+            "ending with *, the beginning of one. Only the first rule that matches a command is applied, e.g.:", "\n",
+            "  lgster -k ABCDEFGH proxy --upstream 192.168.0.2 --rewrite \"VOLUME_CONTROL *=VOLUME_CONTROL 20\" --block \"POWER off=OK\""
+        ))
+        .arg(
+            Arg::with_name("Listen")
+                .long("listen")
+                .value_name("[HOST]:PORT")
+                .help("Where the proxy listens for clients")
+                .takes_value(true)
+                .default_value(":9761"),
+        )
+        .arg(
+            Arg::with_name("Upstream")
+                .long("upstream")
+                .value_name("HOST[:PORT]")
+                .help("The TV the commands are passed on to, which can be the name of a TV profile")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("Rewrite")
+                .long("rewrite")
+                .value_name("PATTERN=COMMAND")
+                .help("Send the TV this command instead of the ones that match the pattern")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("Block")
+                .long("block")
+                .value_name("PATTERN[=REPLY]")
+                .help("Don't send the TV the commands that match the pattern, answering the reply (if any) instead")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )];
+    #[cfg(unix)]
+    subcommands.push(generate_daemon_subcommand());
+    #[cfg(feature = "dbus")]
+    subcommands.push(generate_dbus_subcommand());
+    #[cfg(feature = "dbus")]
+    subcommands.push(generate_follow_sleep_subcommand());
+    subcommands
+}
+
+#[cfg(unix)]
+fn generate_daemon_subcommand<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("daemon")
+        .about("Keep the sessions with the TVs warm for the other lgster commands, which go through it")
+        .long_about(concat!(
+            "Keep the sessions with the TVs warm for the other lgster commands, which go through it", "\n",
+            "\n",
+            "While it runs, the other lgster commands (but not the other modes) hand what they send to the TVs", "\n",
+            "over to it instead (unless they're given --no-daemon, --record or --replay). It derives the key of", "\n",
+            "each TV only once, sends the commands to each TV one at a time in the order they came (so that two", "\n",
+            "scripts don't collide) and answers the queries (e.g. query volume) sent a moment ago from a cache.", "\n",
+            "\n",
+            "Its socket is $LGSTER_SOCKET or, by default, $XDG_RUNTIME_DIR/lgster.sock. It can also be started", "\n",
+            "by systemd when the socket is first used (socket activation), see the README."
+        ))
+        .arg(
+            Arg::with_name("Cache for")
+                .long("cache-for")
+                .value_name("SECONDS")
+                .help("How long the replies to the queries are answered from the cache, with 0 to not cache them")
+                .takes_value(true)
+                .default_value("2"),
+        )
+}
+
+#[cfg(feature = "dbus")]
+fn generate_dbus_subcommand<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("dbus")
+        .about("Serve the TVs on D-Bus as org.lgster.Tv, for desktop widgets and other local tools")
+        .long_about(concat!(
+            "Serve the TVs on D-Bus as org.lgster.Tv, for desktop widgets and other local tools", "\n",
+            "\n",
+            "Each TV is an object under /org/lgster/Tv (e.g. /org/lgster/Tv/lobby) with the org.lgster.Tv", "\n",
+            "interface: the methods Wake, PowerOff, SetVolume, SetMute, SetInput, LaunchApp and PressKey, and the", "\n",
+            "properties Name, Host, Reachable, Volume, Muted and CurrentApp, which are polled and signalled with", "\n",
+            "PropertiesChanged whenever they change. The errors are named after their class (e.g.", "\n",
+            "org.lgster.Tv.Error.Unreachable).", "\n",
+            "\n",
+            "It serves the TVs given by --target-host and --group or, if none, every TV profile."
+        ))
+        .arg(
+            Arg::with_name("System bus")
+                .long("system")
+                .help("Register on the system bus instead of the session bus (which needs a D-Bus policy for it)"),
+        )
+        .arg(
+            Arg::with_name("Interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("How often the properties of the TVs are polled")
+                .takes_value(true)
+                .default_value("10"),
+        )
+}
+
+#[cfg(feature = "dbus")]
+fn generate_follow_sleep_subcommand<'a>() -> App<'a, 'a> {
+    SubCommand::with_name("follow-sleep")
+        .about("Turn the TVs off and on with this computer, when it suspends, shuts down and resumes")
+        .long_about(concat!(
+            "Turn the TVs off and on with this computer, when it suspends, shuts down and resumes", "\n",
+            "\n",
+            "It follows the PrepareForSleep and PrepareForShutdown signals of logind, which it asks to wait", "\n",
+            "until the TVs were turned off. On resume it wakes them up (which needs their MAC address) and", "\n",
+            "switches them to the input of their TV profile (`input = \"hdmi-1\"`) or, if it has none, --input.", "\n",
+            "\n",
+            "It follows with the TVs given by --target-host and --group or, if none, every TV profile."
+        ))
+        .arg(
+            Arg::with_name("Input")
+                .long("input")
+                .value_name("INPUT")
+                .help("The input the TVs are switched to on resume, unless their profile has one (e.g. hdmi-1)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("Wait")
+                .long("wait")
+                .value_name("SECONDS")
+                .help("How long a TV is given to wake up on resume")
+                .takes_value(true)
+                .default_value("60"),
+        )
+}
+
+#[derive(Debug)]
+pub struct DiscoverParameters {
+    pub options: DiscoverOptions,
+    pub write_profiles: bool,
+    pub config: Config,
+    pub config_path: Option<PathBuf>,
+    pub output_format: OutputFormat,
+}
+
+impl DiscoverParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<DiscoverParameters, ParseParameterError> {
+        let (config, config_path) = config_from_matches(matches)?;
+
+        let mut scan = vec![];
+        for cidr in subcommand_matches.values_of("Scan range").into_iter().flatten() {
+            scan.extend(
+                parse_ipv4_cidr(cidr)
+                    .map_err(|e| ParseParameterError::new("--scan", Box::new(e)))?,
+            );
+        }
+        // this is safe to unwrap because it has a default value
+        let timeout = parse_seconds(
+            "--timeout",
+            subcommand_matches.value_of("Timeout").unwrap(),
+            Duration::from_millis(100),
+        )?;
+
+        Ok(DiscoverParameters {
+            options: DiscoverOptions {
+                ssdp: !subcommand_matches.is_present("No SSDP"),
+                timeout,
+                scan,
+                port: port_from_matches(matches)?,
+                // probing is mostly waiting, so it's fine to go well beyond the usual concurrency
+                concurrency: concurrency_from_matches(matches, &config)?.max(64),
+            },
+            write_profiles: subcommand_matches.is_present("Write profiles"),
+            config,
+            config_path,
+            output_format: output_format_from_matches(matches),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MqttParameters {
+    pub options: BridgeOptions,
+    pub targets: Vec<Target>,
+    pub config: Config,
+}
+
+impl MqttParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<MqttParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+        let targets = targets_or_every_profile(matches, &config)?;
+
+        // the given parameters override the ones from the [mqtt] table
+        let settings = config.mqtt.clone().unwrap_or_default();
+        let broker = subcommand_matches
+            .value_of("Broker")
+            .map(str::to_string)
+            .or(settings.broker)
+            .unwrap_or_else(|| "localhost".to_string());
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (
+                host.to_string(),
+                port.parse::<u16>()
+                    .map_err(|e| ParseParameterError::new("--broker", Box::new(e)))?,
+            ),
+            _ => (broker, DEFAULT_MQTT_PORT),
+        };
+        let interval = match subcommand_matches.value_of("Interval") {
+            Some(interval) => interval
+                .parse::<u64>()
+                .map_err(|e| ParseParameterError::new("--interval", Box::new(e)))?,
+            None => settings.interval.unwrap_or(10),
+        };
+        let discovery = !subcommand_matches.is_present("No discovery") && settings.discovery.unwrap_or(true);
+
+        Ok(MqttParameters {
+            options: BridgeOptions {
+                mqtt: MqttOptions {
+                    host,
+                    port,
+                    client_id: format!("lgster-{}", process::id()),
+                    username: subcommand_matches.value_of("Username").map(str::to_string).or(settings.username),
+                    password: subcommand_matches.value_of("Password").map(str::to_string).or(settings.password),
+                    keep_alive: Duration::from_secs(60),
+                    will: None,
+                },
+                prefix: subcommand_matches
+                    .value_of("Prefix")
+                    .map(str::to_string)
+                    .or(settings.prefix)
+                    .unwrap_or_else(|| "lgster".to_string()),
+                discovery_prefix: match discovery {
+                    true => Some(
+                        subcommand_matches
+                            .value_of("Discovery prefix")
+                            .map(str::to_string)
+                            .or(settings.discovery_prefix)
+                            .unwrap_or_else(|| "homeassistant".to_string()),
+                    ),
+                    false => None,
+                },
+                interval: Duration::from_secs(interval.max(1)),
+                concurrency: concurrency_from_matches(matches, &config)?,
+            },
+            targets,
+            config,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct WatchParameters {
+    pub targets: Vec<Target>,
+    pub interval: Duration,
+    /// The properties that are polled at an interval of their own
+    pub intervals: BTreeMap<String, Duration>,
+    pub config: Config,
+    pub output_format: OutputFormat,
+}
+
+impl WatchParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<WatchParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+        let targets = targets_or_every_profile(matches, &config)?;
+
+        // this is safe to unwrap because it has a default value
+        let interval = parse_seconds("--interval", subcommand_matches.value_of("Interval").unwrap(), Duration::ZERO)?;
+        let mut intervals = BTreeMap::new();
+        for property_interval in subcommand_matches.values_of("Property interval").into_iter().flatten() {
+            let (property, seconds) = property_interval.split_once('=').ok_or_else(|| {
+                ParseParameterError::new("--every", "should be PROPERTY=SECONDS (e.g. current-app=1)".into())
+            })?;
+            intervals.insert(property.to_string(), parse_seconds("--every", seconds, Duration::ZERO)?);
+        }
+
+        Ok(WatchParameters {
+            targets,
+            interval,
+            intervals,
+            config,
+            output_format: output_format_from_matches(matches),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduleParameters {
+    pub config: Config,
+    pub concurrency: usize,
+    pub output_format: OutputFormat,
+    /// Only list the schedules instead of running them
+    pub list: bool,
+}
+
+impl ScheduleParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<ScheduleParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+        let concurrency = concurrency_from_matches(matches, &config)?;
+
+        Ok(ScheduleParameters {
+            config,
+            concurrency,
+            output_format: output_format_from_matches(matches),
+            list: subcommand_matches.subcommand_name() == Some("list"),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ProxyParameters {
+    pub options: ProxyOptions,
+    pub upstream: Target,
+    pub output_format: OutputFormat,
+}
+
+impl ProxyParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<ProxyParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+
+        // this is safe to unwrap because it's required
+        let upstream = subcommand_matches.value_of("Upstream").unwrap();
+        let (name, port) = match upstream.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (
+                host,
+                Some(port.parse::<u16>().map_err(|e| ParseParameterError::new("--upstream", Box::new(e)))?),
+            ),
+            _ => (upstream, None),
+        };
+        let mut upstream = Target::resolve(name, &config).map_err(|e| ParseParameterError::new("--upstream", e))?;
+        apply_target_parameters(matches, std::slice::from_mut(&mut upstream))?;
+        if let Some(port) = port {
+            upstream.port = port;
+        }
+
+        // this is safe to unwrap because it has a default value
+        let listen = subcommand_matches.value_of("Listen").unwrap();
+        let listen = match listen.strip_prefix(':') {
+            Some(port) => format!("0.0.0.0:{}", port),
+            None => listen.to_string(),
+        };
+        let listen = listen
+            .to_socket_addrs()
+            .map_err(|e| ParseParameterError::new("--listen", Box::new(e)))?
+            .next()
+            .ok_or_else(|| ParseParameterError::new("--listen", "resolved to no address".into()))?;
+
+        // the rules are kept in the order they were given, whatever their kind
+        let mut rules = vec![];
+        if let (Some(indices), Some(values)) =
+            (subcommand_matches.indices_of("Rewrite"), subcommand_matches.values_of("Rewrite"))
+        {
+            for (index, rule) in indices.zip(values) {
+                let rule = ProxyRule::rewrite(rule).map_err(|e| ParseParameterError::new("--rewrite", Box::new(e)))?;
+                rules.push((index, rule));
+            }
+        }
+        if let (Some(indices), Some(values)) =
+            (subcommand_matches.indices_of("Block"), subcommand_matches.values_of("Block"))
+        {
+            rules.extend(indices.zip(values.map(ProxyRule::block)));
+        }
+        rules.sort_by_key(|(index, _)| *index);
+
+        Ok(ProxyParameters {
+            options: ProxyOptions {
+                listen,
+                rules: rules.into_iter().map(|(_, rule)| rule).collect(),
+            },
+            upstream,
+            output_format: output_format_from_matches(matches),
+        })
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct DaemonParameters {
+    pub options: DaemonOptions,
+    pub socket: PathBuf,
+}
+
+#[cfg(unix)]
+impl DaemonParameters {
+    pub(super) fn try_from_matches(subcommand_matches: &ArgMatches) -> Result<DaemonParameters, ParseParameterError> {
+        // this is safe to unwrap because it has a default value
+        let cache_for = subcommand_matches.value_of("Cache for").unwrap();
+        let cache_for = parse_seconds("--cache-for", cache_for, Duration::ZERO)?;
+
+        Ok(DaemonParameters {
+            options: DaemonOptions {
+                cache_for,
+            },
+            socket: socket_path(),
+        })
+    }
+}
+
+#[cfg(feature = "dbus")]
+#[derive(Debug)]
+pub struct DbusParameters {
+    pub options: DbusOptions,
+    pub targets: Vec<Target>,
+    pub config: Config,
+}
+
+#[cfg(feature = "dbus")]
+impl DbusParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<DbusParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+        let targets = targets_or_every_profile(matches, &config)?;
+        // this is safe to unwrap because it has a default value
+        // polling the TVs nonstop wouldn't do them any good
+        let interval = parse_seconds(
+            "--interval",
+            subcommand_matches.value_of("Interval").unwrap(),
+            Duration::from_secs(1),
+        )?;
+
+        Ok(DbusParameters {
+            options: DbusOptions {
+                bus: match subcommand_matches.is_present("System bus") {
+                    true => Bus::System,
+                    false => Bus::Session,
+                },
+                interval,
+                concurrency: concurrency_from_matches(matches, &config)?,
+            },
+            targets,
+            config,
+        })
+    }
+}
+
+#[cfg(feature = "dbus")]
+#[derive(Debug)]
+pub struct FollowSleepParameters {
+    pub options: SleepOptions,
+    pub targets: Vec<Target>,
+    pub config: Config,
+}
+
+#[cfg(feature = "dbus")]
+impl FollowSleepParameters {
+    pub(super) fn try_from_matches(
+        matches: &ArgMatches,
+        subcommand_matches: &ArgMatches,
+    ) -> Result<FollowSleepParameters, ParseParameterError> {
+        let (config, _) = config_from_matches(matches)?;
+        let targets = targets_or_every_profile(matches, &config)?;
+        // this is safe to unwrap because it has a default value
+        let wait = parse_seconds("--wait", subcommand_matches.value_of("Wait").unwrap(), Duration::ZERO)?;
+
+        Ok(FollowSleepParameters {
+            options: SleepOptions {
+                input: subcommand_matches.value_of("Input").map(|input| input.to_string()),
+                wait,
+                concurrency: concurrency_from_matches(matches, &config)?,
+            },
+            targets,
+            config,
+        })
+    }
+}
+
+/// The TVs given by --target-host and --group or, if none, every TV profile
+fn targets_or_every_profile(matches: &ArgMatches, config: &Config) -> Result<Vec<Target>, ParseParameterError> {
+    if matches.is_present("Target host") || matches.is_present("Target group") {
+        return targets_from_matches(matches, config);
+    }
+
+    config
+        .tvs
+        .keys()
+        .map(|name| Target::resolve(name, config))
+        .collect::<Result<_, _>>()
+        .map_err(|e| ParseParameterError::new("--config", e))
+}
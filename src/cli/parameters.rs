@@ -1,10 +1,31 @@
 use clap::{App, AppSettings, Arg, ArgMatches};
+use log::LevelFilter;
 use std::error::Error;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-use super::commands::{generate_clap_subcommands, Command};
-use super::convert::FixedSizeByteSequenceParameter;
+use super::commands::generate_clap_subcommands;
+#[cfg(unix)]
+use super::modes::DaemonParameters;
+#[cfg(feature = "dbus")]
+use super::modes::DbusParameters;
+#[cfg(feature = "dbus")]
+use super::modes::FollowSleepParameters;
+use super::modes::{
+    generate_mode_subcommands, DiscoverParameters, MqttParameters, ProxyParameters, ScheduleParameters,
+    WatchParameters,
+};
+use crate::bytes::{parse_byte_sequence, Salt};
+use crate::commands::Command;
+use crate::config::Config;
+#[cfg(unix)]
+use crate::daemon::{forward_to_daemon, socket_path};
+use crate::logging::init_logging;
+use crate::schedule::parse_duration;
+use crate::target::Target;
+use crate::transport::{set_transport, RecordingTransport, ReplayTransport};
 
 fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMatches<'a> {
     App::new("lgster")
@@ -23,12 +44,12 @@ fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMa
                 .long_help(concat!(
                     "The keycode generated by the TV when enabling the IP Control function", "\n",
                     "\n",
-                    "This must be the same keycode that your TV generated and is a required parameter.", "\n",
+                    "This must be the same keycode that your TV generated and is a required parameter", "\n",
+                    "(unless --target-host is the name of a TV profile that sets it in the config file).", "\n",
                     "It is used as a shared secret between you and the TV and the message with the command", "\n",
                     "is encrypted with AES-128 CBC with a key derived from it."
                 ))
                 .takes_value(true)
-                .required(true)
         )
         .arg(
             Arg::with_name("Target host")
@@ -37,19 +58,75 @@ fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMa
                 .value_name("x.x.x.x")
                 .next_line_help(true)
                 .long_help(concat!(
-                    "The host of your LG TV on the network", "\n",
-                    "  e.g. 192.168.0.2", "\n",
+                    "The host of your LG TV on the network, or the name of a TV profile from the config file", "\n",
+                    "  e.g. 192.168.0.2 or lobby", "\n",
                     "", "\n",
-                    "If this parameter is not specified, the encrypted message will only be output to the stdout.", "\n",
-                    "The output can then be transmitted through netcat or something similar.", "\n",
-                    "Note that in that case, you will have to handle the response yourself (or ignore it).", "\n",
-                    "For completely silent output (besides the message) use the --quiet parameter. If you", "\n",
-                    "don't, other output will be output through stderr so that the stdout can be redirected.", "\n",
+                    "A TV profile is a [tv.<name>] table with a host and, optionally, the port, keycode, salt", "\n",
+                    "and favourite channels of that TV, e.g.:", "\n",
+                    "  [tv.lobby]", "\n",
+                    "  host = \"192.168.0.2\"", "\n",
+                    "  keycode = \"ABCDEFGH\"", "\n",
+                    "  [tv.lobby.channels]", "\n",
+                    "  news = \"7-1\"", "\n",
                     "", "\n",
-                    "NOTE: Don't append the port to the host. Use the parameter -p if you want to change the default port (9761)."
+                    "Either this parameter or --group is required.", "\n",
+                    "", "\n",
+                    "NOTE: Don't append the port to the host. Use the parameter -p if you want to change the default port (9761).", "\n",
+                    "", "\n",
+                    "It can be given more than once to send the same command to many TVs at the same time."
+                ))
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("Target group")
+                .short("g")
+                .long("group")
+                .value_name("GROUP")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "The name of a group of TVs from the config file to send the command to", "\n",
+                    "", "\n",
+                    "A group is a list of TV profile names or hosts in the [groups] table, e.g.:", "\n",
+                    "  [groups]", "\n",
+                    "  video-wall = [\"wall-1\", \"wall-2\", \"192.168.0.7\"]", "\n",
+                    "", "\n",
+                    "The command is sent to all of them at the same time (see --concurrency) and", "\n",
+                    "the result of each one is reported separately.", "\n",
+                ))
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("Concurrency")
+                .long("concurrency")
+                .value_name("8")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "How many TVs the command is sent to at the same time when there's more than one target", "\n",
+                    "", "\n",
+                    "Defaults to the `concurrency` setting of the config file, or 8 if it's not set.", "\n",
                 ))
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("Output format")
+                .short("o")
+                .long("output")
+                .value_name("FORMAT")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "How the responses are output", "\n",
+                    "", "\n",
+                    "  plain: one line per response (prefixed by the target when there's more than one)", "\n",
+                    "  json:  a JSON object with the responses or the error of each target", "\n",
+                ))
+                .takes_value(true)
+                .possible_values(&["plain", "json"])
+                .default_value("plain")
+        )
         .arg(
             Arg::with_name("Target port")
                 .short("p")
@@ -93,6 +170,25 @@ fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMa
                 ))
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("Config file")
+                .short("c")
+                .long("config")
+                .value_name("~/.config/lgster/config.toml")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "Path of the config file", "\n",
+                    "", "\n",
+                    "Defaults to $XDG_CONFIG_HOME/lgster/config.toml (or ~/.config/lgster/config.toml).", "\n",
+                    "It's fine if the file doesn't exist.", "\n",
+                    "", "\n",
+                    "The [apps] table can be used to extend the app catalog used by `app launch`", "\n",
+                    "and `query current-app` with friendly names for app ids, e.g.:", "\n",
+                    "  [apps]", "\n",
+                    "  crunchyroll = \"crunchyroll.watch\"", "\n",
+                ))
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("Quiet mode")
                 .short("q")
@@ -101,9 +197,6 @@ fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMa
                 .next_line_help(true)
                 .long_help(concat!(
                     "Disables all console output", "\n",
-                    "", "\n",
-                    "If --target-host is unspecified, though, the encrypted message itself", "\n",
-                    "(and nothing more) will be output.", "\n",
                 ))
         )
         .arg(
@@ -111,19 +204,83 @@ fn get_matches<'a, T: IntoIterator<Item = App<'a, 'a>>>(subcommands: T) -> ArgMa
                 .short("d")
                 .long("debug")
                 .takes_value(false)
-                .help("Enables verbose output of each step")
+                .multiple(true)
                 .next_line_help(true)
+                .long_help(concat!(
+                    "Enables verbose output of each step (on stderr)", "\n",
+                    "", "\n",
+                    "That's how the targets were resolved and, for every command, the plaintext, the IV, the", "\n",
+                    "ciphertext, the raw response and the decrypted reply, with how long each step took.", "\n",
+                    "Give it twice (-dd) for even more. The keycodes, keys and passwords are redacted", "\n",
+                    "unless --log-secrets is given too.", "\n",
+                ))
                 .overrides_with("Quiet mode")
         )
+        .arg(
+            Arg::with_name("Log secrets")
+                .long("log-secrets")
+                .takes_value(false)
+                .help("Shows the keycodes, keys and passwords in the --debug output instead of redacting them")
+                .next_line_help(true)
+        )
+        .arg(
+            Arg::with_name("No daemon")
+                .long("no-daemon")
+                .takes_value(false)
+                .help("Sends the commands to the TVs directly even when lgster daemon is running")
+                .next_line_help(true)
+        )
+        .arg(
+            Arg::with_name("Record")
+                .long("record")
+                .value_name("FIXTURE_FILE")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "Saves every exchange with the TVs to this file (appending to it)", "\n",
+                    "", "\n",
+                    "Each line is a JSON object with the TV, the plaintext command, the encrypted request and", "\n",
+                    "response (as hex), the decrypted reply (or the error) and how long it took.", "\n",
+                ))
+                .takes_value(true)
+                .conflicts_with("Replay")
+        )
+        .arg(
+            Arg::with_name("Replay")
+                .long("replay")
+                .value_name("FIXTURE_FILE")
+                .next_line_help(true)
+                .long_help(concat!(
+                    "Answers the commands with the exchanges saved by --record instead of talking to the TVs", "\n",
+                    "", "\n",
+                    "The exchanges of each command to each TV are replayed in the order they were recorded,", "\n",
+                    "with the last one repeated from then on. The keycodes must be the ones used to record", "\n",
+                    "them. Note that wake-on-lan packets are still sent to the network.", "\n",
+                ))
+                .takes_value(true)
+        )
         .subcommands(subcommands)
         .get_matches()
 }
 
 #[derive(Debug)]
-pub struct Salt(pub FixedSizeByteSequenceParameter);
+pub struct IV(pub [u8; 16]);
 
 #[derive(Debug)]
-pub struct IV(pub FixedSizeByteSequenceParameter);
+pub struct MissingParameterError;
+
+impl fmt::Display for MissingParameterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at least one --target-host or --group is required")
+    }
+}
+
+impl Error for MissingParameterError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Plain,
+    Json,
+}
 
 #[derive(Debug)]
 pub struct ParseParameterError {
@@ -131,6 +288,15 @@ pub struct ParseParameterError {
     source_error: Box<dyn Error>,
 }
 
+impl ParseParameterError {
+    pub(super) fn new(parameter_name: &str, source_error: Box<dyn Error>) -> Self {
+        ParseParameterError {
+            parameter_name: parameter_name.to_string(),
+            source_error,
+        }
+    }
+}
+
 impl fmt::Display for ParseParameterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -147,27 +313,11 @@ impl Error for ParseParameterError {
     }
 }
 
-impl FromStr for Salt {
-    type Err = ParseParameterError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let param = FixedSizeByteSequenceParameter::from_string(s.to_string(), '-', 16);
-        match param {
-            Ok(p) => Ok(Salt(p)),
-            Err(e) => Err(ParseParameterError {
-                parameter_name: "--salt".to_string(),
-                source_error: e,
-            }),
-        }
-    }
-}
-
 impl FromStr for IV {
     type Err = ParseParameterError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let param = FixedSizeByteSequenceParameter::from_string(s.to_string(), '-', 16);
-        match param {
+        match parse_byte_sequence(s, '-') {
             Ok(p) => Ok(IV(p)),
             Err(e) => Err(ParseParameterError {
                 parameter_name: "--iv".to_string(),
@@ -179,16 +329,21 @@ impl FromStr for IV {
 
 #[derive(Debug)]
 pub struct Parameters<'a> {
-    pub keycode: String,
-    pub host: Option<String>,
-    pub port: u16,
-    pub salt: Salt,
+    /// Every TV given by --target-host and --group, with --keycode, --target-port
+    /// and --salt (when explicitly given) already applied
+    pub targets: Vec<Target>,
     pub iv: Option<IV>,
+    pub config: Config,
+    pub config_path: Option<PathBuf>,
+    pub concurrency: usize,
+    pub output_format: OutputFormat,
     pub quiet: bool,
     pub debug: bool,
     pub command: &'a Command,
     pub command_action: String,
     pub command_action_parameters: Vec<String>,
+    /// How long to wait before sending the command, from --in (e.g. a sleep timer)
+    pub delay: Option<Duration>,
 }
 
 impl Parameters<'_> {
@@ -196,20 +351,10 @@ impl Parameters<'_> {
         matches: ArgMatches<'a>,
         commands: &'a [Command],
     ) -> Result<Parameters<'a>, ParseParameterError> {
-        // this is safe to unwrap because it's required so clap will validate that for us
-        let keycode: String = matches.value_of("Keycode").unwrap().to_string();
-        let host = matches.value_of("Target host").map(str::to_string);
-
-        // this is safe to unwrap because it has a default value, even if the user don't specify one
-        let port = matches
-            .value_of("Target port")
-            .unwrap()
-            .parse::<u16>()
-            .map_err(|e| ParseParameterError {
-                parameter_name: "--target-port".to_string(),
-                source_error: Box::new(e),
-            })?;
-        let salt = Salt::from_str(matches.value_of("Salt").unwrap())?;
+        let (config, config_path) = config_from_matches(&matches)?;
+        let targets = targets_from_matches(&matches, &config)?;
+        let concurrency = concurrency_from_matches(&matches, &config)?;
+        let output_format = output_format_from_matches(&matches);
         let iv = match matches.value_of("Custom IV") {
             Some(iv_string) => Some(IV::from_str(iv_string)?),
             None => None,
@@ -227,26 +372,262 @@ impl Parameters<'_> {
             .find(|c| c.command_name == command_name)
             .expect("Missing command def. This should never happen!");
 
-        let command_action_parameters = match subcommand_matches.values_of("parameters") {
+        let mut command_action_parameters = match subcommand_matches.values_of("parameters") {
             Some(e) => e.map(String::from).collect::<Vec<String>>(),
             None => vec![]
         };
+        // every command can be delayed, so this one isn't left for the action to deal with
+        let delay = match command_action_parameters.iter().position(|p| p == "--in") {
+            Some(i) => {
+                let duration = command_action_parameters.get(i + 1).ok_or_else(|| {
+                    ParseParameterError::new("--in", "needs a duration (e.g. 45m)".into())
+                })?;
+                let delay = parse_duration(duration).map_err(|e| ParseParameterError::new("--in", Box::new(e)))?;
+                command_action_parameters.drain(i..i + 2);
+                Some(delay)
+            }
+            None => None,
+        };
 
         Ok(Parameters {
-            keycode,
-            host,
-            port,
-            salt,
+            targets,
             iv,
+            config,
+            config_path,
+            concurrency,
+            output_format,
             quiet,
             debug,
             command,
             command_action,
             command_action_parameters,
+            delay,
+        })
+    }
+}
+
+pub(super) fn config_from_matches(
+    matches: &ArgMatches,
+) -> Result<(Config, Option<PathBuf>), ParseParameterError> {
+    let config_path = matches
+        .value_of("Config file")
+        .map(PathBuf::from)
+        .or_else(Config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path).map_err(|e| ParseParameterError {
+            parameter_name: "--config".to_string(),
+            source_error: Box::new(e),
+        })?,
+        None => Config::default(),
+    };
+
+    Ok((config, config_path))
+}
+
+/// Every TV given by --target-host and --group, with --keycode, --target-port
+/// and --salt (when explicitly given) already applied
+pub(super) fn targets_from_matches(
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<Vec<Target>, ParseParameterError> {
+    let mut targets = vec![];
+    for name in matches.values_of("Target host").into_iter().flatten() {
+        targets.push(
+            Target::resolve(name, config).map_err(|e| ParseParameterError {
+                parameter_name: "--target-host".to_string(),
+                source_error: e,
+            })?,
+        );
+    }
+    for group_name in matches.values_of("Target group").into_iter().flatten() {
+        targets.extend(Target::resolve_group(group_name, config).map_err(|e| {
+            ParseParameterError {
+                parameter_name: "--group".to_string(),
+                source_error: e,
+            }
+        })?);
+    }
+    if targets.is_empty() {
+        return Err(ParseParameterError {
+            parameter_name: "--target-host".to_string(),
+            source_error: Box::new(MissingParameterError),
+        });
+    }
+
+    apply_target_parameters(matches, &mut targets)?;
+    Ok(targets)
+}
+
+/// Applies --keycode, --target-port and --salt (when explicitly given) to the targets
+pub(super) fn apply_target_parameters(
+    matches: &ArgMatches,
+    targets: &mut [Target],
+) -> Result<(), ParseParameterError> {
+    // the explicit parameters win over the profile ones, which win over the defaults
+    // (that's why we need to check the occurrences for the ones that have defaults)
+    let keycode = matches.value_of("Keycode");
+    let port = match matches.occurrences_of("Target port") {
+        0 => None,
+        _ => Some(port_from_matches(matches)?),
+    };
+    let salt = match matches.occurrences_of("Salt") {
+        0 => None,
+        _ => Some(
+            Salt::from_str(matches.value_of("Salt").unwrap()).map_err(|e| ParseParameterError::new("--salt", e))?,
+        ),
+    };
+    for target in targets.iter_mut() {
+        if let Some(keycode) = keycode {
+            target.keycode = Some(keycode.to_string());
+        }
+        if let Some(port) = port {
+            target.port = port;
+        }
+        if let Some(Salt(salt)) = salt {
+            target.salt = salt;
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn port_from_matches(matches: &ArgMatches) -> Result<u16, ParseParameterError> {
+    // this is safe to unwrap because it has a default value, even if the user don't specify one
+    matches
+        .value_of("Target port")
+        .unwrap()
+        .parse::<u16>()
+        .map_err(|e| ParseParameterError {
+            parameter_name: "--target-port".to_string(),
+            source_error: Box::new(e),
         })
+}
+
+pub(super) fn concurrency_from_matches(
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<usize, ParseParameterError> {
+    match matches.value_of("Concurrency") {
+        Some(concurrency) => concurrency
+            .parse::<usize>()
+            .map_err(|e| ParseParameterError {
+                parameter_name: "--concurrency".to_string(),
+                source_error: Box::new(e),
+            }),
+        None => Ok(config.concurrency.unwrap_or(8)),
+    }
+}
+
+/// Parses a number of seconds (e.g. 1.5), raised to `minimum` if it's less.
+/// Negative, infinite and too many seconds are errors.
+pub(super) fn parse_seconds(
+    parameter_name: &str,
+    seconds: &str,
+    minimum: Duration,
+) -> Result<Duration, ParseParameterError> {
+    let error = |e: Box<dyn Error>| ParseParameterError::new(parameter_name, e);
+    let seconds = seconds.parse::<f64>().map_err(|e| error(Box::new(e)))?;
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|e| error(Box::new(e)))?;
+    Ok(duration.max(minimum))
+}
+
+fn set_transport_from_matches(matches: &ArgMatches) -> Result<(), ParseParameterError> {
+    if let Some(path) = matches.value_of("Record") {
+        let recording_transport = RecordingTransport::create(Path::new(path))
+            .map_err(|e| ParseParameterError::new("--record", Box::new(e)))?;
+        set_transport(Box::new(recording_transport));
+    }
+    if let Some(path) = matches.value_of("Replay") {
+        let replay_transport = ReplayTransport::open(Path::new(path))
+            .map_err(|e| ParseParameterError::new("--replay", Box::new(e)))?;
+        set_transport(Box::new(replay_transport));
+    }
+    Ok(())
+}
+
+fn log_level_from_matches(matches: &ArgMatches) -> LevelFilter {
+    match (matches.is_present("Quiet mode"), matches.occurrences_of("Debug")) {
+        (true, _) => LevelFilter::Off,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+pub(super) fn output_format_from_matches(matches: &ArgMatches) -> OutputFormat {
+    // this is safe to unwrap because it has a default value and clap validates the possible values
+    match matches.value_of("Output format").unwrap() {
+        "json" => OutputFormat::Json,
+        _ => OutputFormat::Plain,
     }
 }
 
-pub fn get_parameters<'a>(commands: &'a [Command]) -> Result<Parameters<'a>, ParseParameterError> {
-    Parameters::try_from_matches(get_matches(generate_clap_subcommands(&commands)), &commands)
+/// What lgster was asked to do: either send one of the commands to the TVs
+/// or run one of the modes that aren't a simple command
+#[derive(Debug)]
+pub enum Invocation<'a> {
+    Command(Parameters<'a>),
+    Discover(DiscoverParameters),
+    Mqtt(MqttParameters),
+    Watch(WatchParameters),
+    Schedule(ScheduleParameters),
+    Proxy(ProxyParameters),
+    #[cfg(unix)]
+    Daemon(DaemonParameters),
+    #[cfg(feature = "dbus")]
+    Dbus(DbusParameters),
+    #[cfg(feature = "dbus")]
+    FollowSleep(FollowSleepParameters),
+}
+
+pub fn get_parameters<'a>(commands: &'a [Command]) -> Result<Invocation<'a>, ParseParameterError> {
+    let subcommands = generate_clap_subcommands(commands)
+        .into_iter()
+        .chain(generate_mode_subcommands());
+    let matches = get_matches(subcommands);
+    // right away, so that what's done while parsing (e.g. resolving the targets) is logged too
+    init_logging(log_level_from_matches(&matches), matches.is_present("Log secrets"));
+    set_transport_from_matches(&matches)?;
+
+    match matches.subcommand() {
+        ("discover", Some(subcommand_matches)) => Ok(Invocation::Discover(
+            DiscoverParameters::try_from_matches(&matches, subcommand_matches)?,
+        )),
+        ("mqtt", Some(subcommand_matches)) => Ok(Invocation::Mqtt(MqttParameters::try_from_matches(
+            &matches,
+            subcommand_matches,
+        )?)),
+        ("watch", Some(subcommand_matches)) => Ok(Invocation::Watch(WatchParameters::try_from_matches(
+            &matches,
+            subcommand_matches,
+        )?)),
+        ("schedule", Some(subcommand_matches)) => Ok(Invocation::Schedule(
+            ScheduleParameters::try_from_matches(&matches, subcommand_matches)?,
+        )),
+        ("proxy", Some(subcommand_matches)) => Ok(Invocation::Proxy(ProxyParameters::try_from_matches(
+            &matches,
+            subcommand_matches,
+        )?)),
+        #[cfg(unix)]
+        ("daemon", Some(subcommand_matches)) => {
+            Ok(Invocation::Daemon(DaemonParameters::try_from_matches(subcommand_matches)?))
+        }
+        #[cfg(feature = "dbus")]
+        ("dbus", Some(subcommand_matches)) => Ok(Invocation::Dbus(DbusParameters::try_from_matches(
+            &matches,
+            subcommand_matches,
+        )?)),
+        #[cfg(feature = "dbus")]
+        ("follow-sleep", Some(subcommand_matches)) => Ok(Invocation::FollowSleep(
+            FollowSleepParameters::try_from_matches(&matches, subcommand_matches)?,
+        )),
+        _ => {
+            // the recordings are of this process, so those go to the TVs directly
+            #[cfg(unix)]
+            if !["No daemon", "Record", "Replay"].iter().any(|name| matches.is_present(name)) {
+                forward_to_daemon(socket_path());
+            }
+            Ok(Invocation::Command(Parameters::try_from_matches(matches, commands)?))
+        }
+    }
 }
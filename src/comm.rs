@@ -1,13 +1,19 @@
+use log::{debug, trace};
 use rand::Rng;
-use std::convert::TryInto;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use crate::cli::FixedSizeByteSequenceParameter;
-use crate::crypto::{
-    decrypt_iv, decrypt_message, derive_aes_key_from_keycode, encrypt_iv, encrypt_message,
-};
+use crate::bytes::MacAddress;
+use crate::crypto::{self, derive_aes_key_from_keycode, ProtocolError};
+use crate::logging::{Hex, Secret};
+use crate::metrics::record_key_derivation;
 use crate::network;
+use crate::transport::{exchange_of, transport};
 
 #[derive(Debug)]
 pub enum CommunicationError {
@@ -23,19 +29,115 @@ impl fmt::Display for CommunicationError {
 }
 impl Error for CommunicationError {}
 
-pub fn send_wol_packet(target_ip: &str, target_mac_address: &str) -> Result<usize, Box<dyn Error>> {
-    let mac = FixedSizeByteSequenceParameter::from_string(target_mac_address.to_string(), ':', 6)?;
-    const MAGIC_PACKET_HEADER: [u8; 6] = [0xFF; 6];
+#[derive(Debug, Clone)]
+pub struct WakeOptions {
+    /// The UDP port the packet is sent to (usually 9 or 7)
+    pub port: u16,
+    /// The SecureOn password, for the network cards that require one
+    pub password: Option<[u8; 6]>,
+    /// How many packets are sent, since a single one can easily get lost
+    pub repeat: u32,
+    /// How long to wait between each packet
+    pub interval: Duration,
+    /// The network interface the packet is sent from (also the scope of link-local IPv6 targets)
+    pub interface: Option<String>,
+    /// The source address the packet is sent from
+    pub source_address: Option<IpAddr>,
+}
+
+impl Default for WakeOptions {
+    fn default() -> Self {
+        WakeOptions {
+            port: 9,
+            password: None,
+            repeat: 1,
+            interval: Duration::from_millis(100),
+            interface: None,
+            source_address: None,
+        }
+    }
+}
 
-    let mut magic_packet = [0u8; 102];
+/// Sends the wake-on-lan magic packet for `target_mac_address` to `target_ip`, which is usually
+/// a broadcast address but can also be an IPv6 multicast one (e.g. `ff02::1%eth0`).
+/// Returns how many bytes were sent in total.
+pub fn send_wol_packet(
+    target_ip: &str,
+    target_mac_address: &str,
+    options: &WakeOptions,
+) -> Result<usize, Box<dyn Error>> {
+    let mac: MacAddress = target_mac_address.parse()?;
+    const MAGIC_PACKET_HEADER: [u8; 6] = [0xFF; 6];
 
-    MAGIC_PACKET_HEADER
+    let mut magic_packet = Vec::with_capacity(108);
+    magic_packet.extend_from_slice(&MAGIC_PACKET_HEADER);
+    [&mac.0]
         .iter()
-        .chain([mac.bytes].iter().cycle().take(16).flatten())
-        .enumerate()
-        .for_each(|(i, byte)| magic_packet[i] = *byte);
+        .cycle()
+        .take(16)
+        .for_each(|mac| magic_packet.extend_from_slice(*mac));
+    if let Some(password) = &options.password {
+        magic_packet.extend_from_slice(password);
+    }
 
-    network::send_udp_message(target_ip, &magic_packet).map_err(|e| e.into())
+    let target = network::resolve_udp_target(target_ip, options.port, options.interface.as_deref())?;
+    debug!(
+        "sending wake-on-lan for mac={} to target={} password={} repeat={}",
+        target_mac_address,
+        target,
+        match &options.password {
+            Some(password) => Secret(Hex(password)).to_string(),
+            None => "none".to_string(),
+        },
+        options.repeat
+    );
+    let mut bytes_sent = 0;
+    for i in 0..options.repeat.max(1) {
+        if i > 0 {
+            sleep(options.interval);
+        }
+        bytes_sent += network::send_udp_message(
+            target,
+            &magic_packet,
+            options.source_address,
+            options.interface.as_deref(),
+        )?;
+    }
+
+    Ok(bytes_sent)
+}
+
+// keycode and salt -> AES key
+type AesKeyCache = Mutex<HashMap<(String, [u8; 16]), [u8; 16]>>;
+
+/// The AES key of a keycode, derived only once per process, since deriving it takes a
+/// noticeable while (PBKDF2 with 2^14 iterations). This is what keeps long-running
+/// processes like lgster-server warm.
+pub fn aes_key_for(keycode: &str, salt: &[u8; 16]) -> [u8; 16] {
+    static AES_KEYS: OnceLock<AesKeyCache> = OnceLock::new();
+
+    let aes_keys = AES_KEYS.get_or_init(Default::default);
+    if let Some(aes_key) = aes_keys.lock().unwrap().get(&(keycode.to_string(), *salt)) {
+        trace!("reusing the AES key of keycode={}", Secret(keycode));
+        return *aes_key;
+    }
+
+    // derived without holding the lock, so that the keys of other TVs aren't held up by it
+    let started_at = Instant::now();
+    let aes_key = derive_aes_key_from_keycode(keycode, salt);
+    record_key_derivation();
+    debug!(
+        "derived key={} from keycode={} salt={} took={:?}",
+        Secret(Hex(&aes_key)),
+        Secret(keycode),
+        Hex(salt),
+        started_at.elapsed()
+    );
+    aes_keys
+        .lock()
+        .unwrap()
+        .insert((keycode.to_string(), *salt), aes_key);
+    aes_key
 }
 
 pub fn send_command(
@@ -43,35 +145,62 @@ pub fn send_command(
     port: u16,
     keycode: &str,
     salt: &[u8; 16],
-    mut command: String,
+    command: String,
 ) -> Result<String, Box<dyn Error>> {
-    let aes_key = derive_aes_key_from_keycode(keycode, salt);
+    let aes_key = aes_key_for(keycode, salt);
     let randomly_generated_iv: [u8; 16] = rand::thread_rng().gen();
 
-    command.push('\r');
-    let mut encrypted_message = encrypt_iv(&randomly_generated_iv, &aes_key);
-    encrypted_message.extend_from_slice(&encrypt_message(
-        command.as_str(),
-        &randomly_generated_iv,
-        &aes_key,
-    ));
+    let started_at = Instant::now();
+    debug!("sending command={:?} to host={} port={} iv={}", command, host, port, Hex(&randomly_generated_iv));
+    let encrypted_message = encrypt_command(&command, &randomly_generated_iv, &aes_key);
+    debug!("ciphertext={} took={:?}", Hex(&encrypted_message), started_at.elapsed());
 
-    let response = match network::send_and_receive_tcp_message((host, port), &encrypted_message) {
+    let transport = transport();
+    let started_at = Instant::now();
+    let response = match transport.exchange(host, port, &command, &encrypted_message) {
         Ok(response_bytes) => response_bytes,
-        Err(e) => return Err(Box::new(e)),
+        Err(e) => {
+            let took = started_at.elapsed();
+            debug!("no response from host={} port={} error={:?} took={:?}", host, port, e, took);
+            transport.exchanged(&exchange_of(host, port, &command, &encrypted_message, Err(&e), took));
+            return Err(Box::new(e));
+        }
     };
-    let (encrypted_response_iv, encrypted_response_message) = response.split_at(16);
-    let encrypted_response_iv: &[u8; 16] = encrypted_response_iv
-        .try_into()
-        .expect("Response IV wasn't 16 bytes. This should never happen!");
+    let took = started_at.elapsed();
+    debug!("response={} took={:?}", Hex(&response), took);
 
-    let decrypted_response_iv = decrypt_iv(&encrypted_response_iv, &aes_key);
-    let decrypted_response_message =
-        decrypt_message(encrypted_response_message, &decrypted_response_iv, &aes_key);
+    let started_at = Instant::now();
+    let reply = decrypt_reply(&response, &aes_key)?;
+    debug!("reply={:?} took={:?}", reply, started_at.elapsed());
 
-    let null_char_position = decrypted_response_message
-        .iter()
-        .position(|b| *b == 0)
-        .unwrap_or(decrypted_response_message.len());
-    Ok(String::from_utf8_lossy(&decrypted_response_message[..null_char_position]).into_owned())
+    let outcome = Ok((response.as_slice(), reply.as_str()));
+    transport.exchanged(&exchange_of(host, port, &command, &encrypted_message, outcome, took));
+    Ok(reply)
+}
+
+/// A command as the TVs expect it: the IV encrypted on its own, followed by the command
+/// (ending with a carriage return) encrypted with it
+pub fn encrypt_command(command: &str, iv: &[u8; 16], aes_key: &[u8; 16]) -> Vec<u8> {
+    lgster_core::encrypt_command_to_vec(command, iv, aes_key)
+}
+
+/// The command of a message made like `encrypt_command` does (e.g. by another controller), or None
+/// when it isn't a whole one, which is usually because it was encrypted with another keycode
+pub fn decrypt_command(message: &[u8], aes_key: &[u8; 16]) -> Option<String> {
+    let mut message = message.to_vec();
+    let command = crypto::decrypt_command(&mut message, aes_key).ok()?;
+    String::from_utf8(command.to_vec()).ok()
+}
+
+/// A reply as the TVs send it: the IV encrypted on its own, followed by the reply (ending with a
+/// line feed) zero-padded and encrypted with it
+pub fn encrypt_reply(reply: &str, iv: &[u8; 16], aes_key: &[u8; 16]) -> Vec<u8> {
+    lgster_core::encrypt_reply_to_vec(reply, iv, aes_key)
+}
+
+/// The reply of a response made like `encrypt_reply` does, up to its padding
+pub fn decrypt_reply(response: &[u8], aes_key: &[u8; 16]) -> Result<String, ProtocolError> {
+    let mut response = response.to_vec();
+    let reply = crypto::decrypt_reply(&mut response, aes_key)?;
+    Ok(String::from_utf8_lossy(reply).into_owned())
 }
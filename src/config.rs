@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "config-file")]
+use std::fs;
+#[cfg(feature = "config-file")]
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::PathBuf;
+#[cfg(feature = "config-file")]
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    source_error: Box<dyn Error>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Error while accessing config file {} ({})",
+            self.path.display(),
+            self.source_error
+        )
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source_error)
+    }
+}
+
+/// A TV that can be targeted by name (e.g. `-t lobby`) instead of by host
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TvProfile {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keycode: Option<String>,
+    /// Same format as the --salt parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    /// Used to wake the TV up, since it's not reachable by IP while it's off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    /// Favourite channels, mapping a name to a channel (e.g. `news = "7-1"`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, String>,
+    /// How the TV is woken up, when the defaults aren't enough
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wake: Option<WakeSettings>,
+    /// The input it's switched to when the computer it's connected to resumes (e.g. `hdmi-1`),
+    /// see lgster follow-sleep
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+}
+
+/// The `[tv.<name>.wake]` table, with the same meaning as the parameters of lgster-wake
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct WakeSettings {
+    /// Where the packet is sent, usually the broadcast address of the TV's network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// SecureOn password, in the same format as a MAC address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_address: Option<IpAddr>,
+}
+
+/// The `[server]` table, for lgster-server
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ServerSettings {
+    /// The address the HTTP server listens on (127.0.0.1 by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// The token every request must bring as `Authorization: Bearer <token>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// A Unix socket to listen on as well, which only the user running the server may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+    /// How often the state of the TV profiles is polled for /metrics, in seconds (0 to not poll)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_interval: Option<u64>,
+    /// How many requests of each listener are answered at the same time (8 by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+}
+
+/// The `[mqtt]` table, for `lgster mqtt`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct MqttSettings {
+    /// The host of the broker, optionally with the port (e.g. `localhost:1883`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broker: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// The first level of every topic (`lgster` by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Where the Home Assistant discovery payloads are published (`homeassistant` by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery_prefix: Option<String>,
+    /// Whether to publish the Home Assistant discovery payloads at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<bool>,
+    /// How often the state of the TVs is polled, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+}
+
+/// A `[[hook]]` table, for what `lgster watch` does when the state of a TV changes
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct HookSettings {
+    /// Shown when it fails (the property by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The TV profiles or hosts it's for (every TV watched by default)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tvs: Vec<String>,
+    /// The property it's about (e.g. `current-app` or `reachable`)
+    pub property: String,
+    /// The value the property must change from, either exactly or, ending with `*`, by prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// The value the property must change to, like `from` (e.g. `hdmi-*`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// A shell command to run, which gets the change in LGSTER_* environment variables
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// An `http://` URL to POST the change to as JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// How long the new value must last before it runs, in seconds (0 by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debounce: Option<u64>,
+    /// How long it may take before it's given up on (and the command killed), in seconds (10 by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// A `[[schedule]]` table, for `lgster schedule`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct ScheduleSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// When it runs, in local time (e.g. `weekdays 08:00` or `22:30`)
+    pub at: String,
+    /// The TV profiles, hosts or groups it's for (every TV profile by default)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tvs: Vec<String>,
+    /// The commands it runs one after the other, as they're given to lgster (e.g. `power on --wait`)
+    pub run: Vec<String>,
+    /// What's done about a run that was missed, because lgster wasn't running or the machine was asleep
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missed: Option<MissedRuns>,
+    /// How late a missed run can still be caught up on (e.g. `2h`, 1 hour by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grace: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissedRuns {
+    /// Only run on time (the default)
+    Skip,
+    /// Run as soon as possible, if it's still within the grace period
+    CatchUp,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    /// How many TVs a command is sent to at the same time when targeting more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Extra entries for the app catalog, mapping a friendly name to a webOS app id.
+    /// These take precedence over the bundled ones with the same name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub apps: BTreeMap<String, String>,
+    #[serde(default, rename = "tv", skip_serializing_if = "BTreeMap::is_empty")]
+    pub tvs: BTreeMap<String, TvProfile>,
+    /// Named lists of TVs (profile names or hosts) that can be targeted at once with --group
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<ServerSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttSettings>,
+    #[serde(default, rename = "hook", skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookSettings>,
+    #[serde(default, rename = "schedule", skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleSettings>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/lgster/config.toml`, falling back to `~/.config/lgster/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(config_home.join("lgster").join("config.toml"))
+    }
+
+    /// Loads the config file at `path`. A missing file is not an error and
+    /// just results in the default (empty) config.
+    #[cfg(feature = "config-file")]
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => {
+                return Err(ConfigError {
+                    path: path.to_path_buf(),
+                    source_error: Box::new(e),
+                })
+            }
+        };
+
+        toml::from_str(&contents).map_err(|e| ConfigError {
+            path: path.to_path_buf(),
+            source_error: Box::new(e),
+        })
+    }
+
+    /// Writes the config back to `path`, creating its directory if needed.
+    /// Note that comments and formatting of an existing file are not preserved.
+    #[cfg(feature = "config-file")]
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let to_config_error = |e: Box<dyn Error>| ConfigError {
+            path: path.to_path_buf(),
+            source_error: e,
+        };
+
+        let contents = toml::to_string(self).map_err(|e| to_config_error(Box::new(e)))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| to_config_error(Box::new(e)))?;
+        }
+        fs::write(path, contents).map_err(|e| to_config_error(Box::new(e)))
+    }
+}
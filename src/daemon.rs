@@ -0,0 +1,376 @@
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Error as IOError, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bytes::Salt;
+use crate::commands::Command;
+use crate::target::Target;
+use crate::transport::error_kind_from_name;
+
+// way longer than any command takes, even when the TV doesn't answer
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(60);
+// the first file descriptor systemd passes with socket activation (see sd_listen_fds)
+const SD_LISTEN_FDS_START: i32 = 3;
+// a session with a TV that got no commands for this long ends (the keys stay derived anyway)
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// A command for the daemon to send, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+struct DaemonRequest {
+    name: String,
+    host: String,
+    port: u16,
+    keycode: String,
+    /// Like the --salt parameter
+    salt: String,
+    command: String,
+}
+
+/// What the daemon answers to each request, one JSON object per line
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DaemonResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error_kind: Option<String>,
+    /// Whether the reply was a recent one of the same query instead of a fresh one
+    #[serde(default)]
+    cached: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// How long the replies to the queries of the command catalog are answered from the cache
+    pub cache_for: Duration,
+}
+
+/// A command for a TV, with the credentials it came with (each request brings its own, so that
+/// a wrong keycode fails like it should), and where to answer it
+type Job = (Target, String, Sender<DaemonResponse>);
+
+/// A TV, by its host and port, whatever the credentials of the commands to it
+type SessionKey = (String, u16);
+
+type Sessions = Arc<Mutex<HashMap<SessionKey, Sender<Job>>>>;
+
+/// The socket of the daemon, which is $LGSTER_SOCKET or, by default, lgster.sock in the runtime
+/// directory of the user
+pub fn socket_path() -> PathBuf {
+    if let Some(path) = env::var_os("LGSTER_SOCKET") {
+        return PathBuf::from(path);
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => Path::new(&runtime_dir).join("lgster.sock"),
+        // getuid can't fail
+        None => env::temp_dir().join(format!("lgster-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+/// The socket systemd passed to the process, when it was started by socket activation
+pub fn listener_from_systemd() -> Option<UnixListener> {
+    let for_this_process = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()? == process::id();
+    let passed_sockets = env::var("LISTEN_FDS").ok()?.parse::<u32>().ok()?;
+    // so that the processes it runs (e.g. the hooks) don't take them for theirs
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    if !for_this_process || passed_sockets == 0 {
+        return None;
+    }
+
+    // safe because systemd passed this descriptor to this process, and nothing else uses it
+    Some(unsafe { UnixListener::from_raw_fd(SD_LISTEN_FDS_START) })
+}
+
+/// Listens at `path` (only for the user), replacing the socket a daemon that's gone left behind
+pub fn bind_socket(path: &Path) -> io::Result<UnixListener> {
+    let listener = match UnixListener::bind(path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(IOError::new(
+                    ErrorKind::AddrInUse,
+                    format!("another daemon is already listening at {}", path.display()),
+                ));
+            }
+            debug!("removing the stale socket at path={}", path.display());
+            fs::remove_file(path)?;
+            UnixListener::bind(path)?
+        }
+        result => result?,
+    };
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serves the commands forwarded by other lgster processes (see `forward_to_daemon`). Each TV
+/// (by its host and port, whatever keycode the commands come with) gets a session of its own, a
+/// thread that sends its commands one at a time in the order they came, so that they don't
+/// collide, and answers the queries of the catalog that were sent a moment ago from a cache. A
+/// session ends once it's idle for a while, but the keys stay derived for as long as it runs. It
+/// only returns when it can't accept connections anymore.
+pub fn run_daemon(listener: UnixListener, options: &DaemonOptions, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    let queries = query_commands(commands);
+    let sessions: Sessions = Arc::default();
+
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // e.g. too many open files, which can go away by itself
+                    warn!("couldn't accept a client error={}", e);
+                    continue;
+                }
+            };
+            let (sessions, queries) = (&sessions, &queries);
+            scope.spawn(move || {
+                if let Err(e) = serve_client(stream, sessions, queries, options) {
+                    debug!("lost a client error={}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// The IP Control commands of the queries of the catalog, which are the only ones that are
+/// cached (any other command to a TV drops what's cached for it, since it may change it)
+fn query_commands(commands: &[Command]) -> HashSet<String> {
+    commands
+        .iter()
+        .filter(|command| command.command_name == "query")
+        .flat_map(|command| command.command_actions.iter())
+        .flat_map(|(_, ip_control_commands)| ip_control_commands.iter())
+        .map(|ip_control_command| ip_control_command.to_string())
+        .collect()
+}
+
+/// Answers every request a client sends, until it closes the connection
+fn serve_client(
+    stream: UnixStream,
+    sessions: &Sessions,
+    queries: &HashSet<String>,
+    options: &DaemonOptions,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<DaemonRequest>(&line) {
+            Ok(request) => handle_request(request, sessions, queries, options),
+            Err(e) => DaemonResponse {
+                error: Some(format!("Invalid request ({})", e)),
+                error_kind: Some(format!("{:?}", ErrorKind::InvalidInput)),
+                ..Default::default()
+            },
+        };
+        // serializing this plain struct can't fail
+        let mut response = serde_json::to_string(&response).unwrap();
+        response.push('\n');
+        writer.write_all(response.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn handle_request(
+    request: DaemonRequest,
+    sessions: &Sessions,
+    queries: &HashSet<String>,
+    options: &DaemonOptions,
+) -> DaemonResponse {
+    let salt = match request.salt.parse::<Salt>() {
+        Ok(Salt(salt)) => salt,
+        Err(e) => {
+            return DaemonResponse {
+                error: Some(format!("Invalid salt ({})", e)),
+                error_kind: Some(format!("{:?}", ErrorKind::InvalidInput)),
+                ..Default::default()
+            }
+        }
+    };
+    let key = (request.host.clone(), request.port);
+    let target = Target {
+        name: request.name,
+        profile: None,
+        host: request.host,
+        port: request.port,
+        keycode: Some(request.keycode),
+        salt,
+    };
+
+    let (respond, response) = mpsc::channel();
+    let job = (target, request.command, respond);
+    let queued = {
+        let mut sessions_by_tv = sessions.lock().unwrap();
+        let queue = sessions_by_tv
+            .entry(key.clone())
+            .or_insert_with(|| start_session(key.clone(), sessions, queries, options));
+        match queue.send(job) {
+            Ok(()) => Ok(()),
+            // its thread is gone (which it only is if it panicked), so it gets a new one
+            Err(mpsc::SendError(job)) => {
+                let queue = start_session(key.clone(), sessions, queries, options);
+                let queued = queue.send(job);
+                sessions_by_tv.insert(key, queue);
+                queued
+            }
+        }
+    };
+
+    match queued.ok().and_then(|_| response.recv().ok()) {
+        Some(response) => response,
+        None => DaemonResponse {
+            error: Some("The session with the TV ended unexpectedly (this is a bug)".to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+fn start_session(key: SessionKey, sessions: &Sessions, queries: &HashSet<String>, options: &DaemonOptions) -> Sender<Job> {
+    let (queue, jobs) = mpsc::channel();
+    let (sessions, queries, cache_for) = (sessions.clone(), queries.clone(), options.cache_for);
+    debug!("starting a session with host={} port={}", key.0, key.1);
+    thread::spawn(move || run_session(key, jobs, &sessions, &queries, cache_for));
+    queue
+}
+
+/// Sends the commands to a TV one at a time, until it got none for a while
+fn run_session(key: SessionKey, jobs: Receiver<Job>, sessions: &Sessions, queries: &HashSet<String>, cache_for: Duration) {
+    let mut cache = Cache::new();
+
+    loop {
+        let (target, command, respond) = match jobs.recv_timeout(SESSION_IDLE_TIMEOUT) {
+            Ok(job) => job,
+            Err(RecvTimeoutError::Timeout) => {
+                // jobs are only queued while holding the lock, so none can come in between
+                let mut sessions = sessions.lock().unwrap();
+                match jobs.try_recv() {
+                    Ok(job) => job,
+                    Err(_) => {
+                        debug!("ending the idle session with host={} port={}", key.0, key.1);
+                        sessions.remove(&key);
+                        return;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let response = respond_to(&target, command, &mut cache, queries, cache_for);
+        // the client may be gone already, which is fine
+        let _ = respond.send(response);
+    }
+}
+
+/// The replies to the queries of the catalog that were sent a moment ago, by the credentials they
+/// were sent with too, so that a reply is only ever given to requests with the same ones
+type Cache = HashMap<(String, [u8; 16], String), (Instant, String)>;
+
+fn respond_to(target: &Target, command: String, cache: &mut Cache, queries: &HashSet<String>, cache_for: Duration) -> DaemonResponse {
+    if !queries.contains(&command) {
+        cache.clear();
+    }
+    // the requests always bring a keycode
+    let cache_key = (target.keycode.clone().unwrap_or_default(), target.salt, command);
+    match cache.get(&cache_key) {
+        Some((replied_at, reply)) if replied_at.elapsed() < cache_for => {
+            debug!("answering command={:?} to host={} from the cache", cache_key.2, target.host);
+            DaemonResponse {
+                reply: Some(reply.clone()),
+                cached: true,
+                ..Default::default()
+            }
+        }
+        _ => match target.send(cache_key.2.clone()) {
+            Ok(reply) => {
+                if queries.contains(&cache_key.2) {
+                    cache.insert(cache_key, (Instant::now(), reply.clone()));
+                }
+                DaemonResponse {
+                    reply: Some(reply),
+                    ..Default::default()
+                }
+            }
+            Err(e) => DaemonResponse {
+                error: Some(e.to_string()),
+                error_kind: e.downcast_ref::<IOError>().map(|e| format!("{:?}", e.kind())),
+                ..Default::default()
+            },
+        },
+    }
+}
+
+static DAEMON_SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes `Target::send` forward the commands to the daemon listening at `path` whenever it's
+/// running, which can only be set once per process. Returns whether it was set.
+pub fn forward_to_daemon(path: PathBuf) -> bool {
+    DAEMON_SOCKET.set(path).is_ok()
+}
+
+/// Sends a command through the daemon, or returns None when there's no daemon to forward it to
+/// (in which case it should be sent directly)
+pub(crate) fn send_through_daemon(target: &Target, keycode: &str, command: &str) -> Option<Result<String, Box<dyn Error>>> {
+    let path = DAEMON_SOCKET.get()?;
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("no daemon at path={} error={}", path.display(), e);
+            return None;
+        }
+    };
+
+    let request = DaemonRequest {
+        name: target.name.clone(),
+        host: target.host.clone(),
+        port: target.port,
+        keycode: keycode.to_string(),
+        salt: Salt(target.salt).to_string(),
+        command: command.to_string(),
+    };
+    debug!("forwarding command={:?} to host={} through the daemon at path={}", command, target.host, path.display());
+    Some(exchange_with_daemon(stream, &request))
+}
+
+fn exchange_with_daemon(mut stream: UnixStream, request: &DaemonRequest) -> Result<String, Box<dyn Error>> {
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(Box::new(IOError::new(
+            ErrorKind::UnexpectedEof,
+            "the daemon closed the connection without answering",
+        )));
+    }
+
+    let response: DaemonResponse = serde_json::from_str(&line)?;
+    debug!("the daemon answered reply={:?} cached={}", response.reply, response.cached);
+    match (response.reply, response.error) {
+        (Some(reply), _) => Ok(reply),
+        (None, error) => Err(Box::new(IOError::new(
+            error_kind_from_name(response.error_kind.as_deref()),
+            error.unwrap_or_else(|| "the daemon answered neither a reply nor an error".to_string()),
+        ))),
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use zbus::blocking::connection::Builder;
+use zbus::names::BusName;
+use zbus::zvariant::Value;
+use zbus::{fdo, interface, DBusError};
+
+use crate::actions::run_action;
+use crate::apps::AppCatalog;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::error::ErrorClass;
+use crate::fanout::run_concurrently;
+use crate::target::Target;
+use crate::watch::{Watcher, REACHABLE};
+
+/// The well-known name of the service, which is also the name of the interface of each TV
+pub const DBUS_NAME: &str = "org.lgster.Tv";
+/// Every TV is an object under it (e.g. `/org/lgster/Tv/lobby`), which is also where the
+/// ObjectManager that lists them is
+pub const DBUS_PATH: &str = "/org/lgster/Tv";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+#[derive(Debug, Clone)]
+pub struct DbusOptions {
+    pub bus: Bus,
+    /// How often the properties of the TVs are polled
+    pub interval: Duration,
+    pub concurrency: usize,
+}
+
+/// The errors of the methods, one D-Bus error name per `ErrorClass` (e.g. `org.lgster.Tv.Error.Unreachable`)
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.lgster.Tv.Error")]
+pub enum TvError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    InvalidRequest(String),
+    NotFound(String),
+    Unauthorized(String),
+    NotConfigured(String),
+    Unreachable(String),
+    Timeout(String),
+    Internal(String),
+}
+
+impl From<Box<dyn Error>> for TvError {
+    fn from(error: Box<dyn Error>) -> Self {
+        let message = error.to_string();
+        match ErrorClass::of(error.as_ref()) {
+            ErrorClass::InvalidRequest => TvError::InvalidRequest(message),
+            ErrorClass::NotFound => TvError::NotFound(message),
+            ErrorClass::Unauthorized => TvError::Unauthorized(message),
+            ErrorClass::NotConfigured => TvError::NotConfigured(message),
+            ErrorClass::Unreachable => TvError::Unreachable(message),
+            ErrorClass::Timeout => TvError::Timeout(message),
+            ErrorClass::Internal => TvError::Internal(message),
+        }
+    }
+}
+
+/// The object of a TV, whose properties are the last polled ones
+struct TvObject {
+    target: Target,
+    config: Arc<Config>,
+    commands: Arc<Vec<Command>>,
+    state: Arc<Mutex<BTreeMap<&'static str, String>>>,
+    poll_now: Arc<AtomicBool>,
+}
+
+impl TvObject {
+    /// Runs an action of the command catalog on a thread of its own, so that a TV that takes
+    /// its time doesn't hold up the calls to the other ones. The reply must be OK.
+    async fn run_ok(&self, command_name: &'static str, action: &str, parameters: Vec<String>) -> Result<(), TvError> {
+        let (target, config, commands) = (self.target.clone(), self.config.clone(), self.commands.clone());
+        let action = action.to_string();
+        let (reply, action) = blocking::unblock(move || {
+            // the catalog always has the commands the methods use
+            let command = commands.iter().find(|command| command.command_name == command_name).unwrap();
+            let app_catalog = AppCatalog::with_user_apps(&config.apps);
+            run_action(command, &action, &parameters, &target, &config, &app_catalog)
+                .map(|replies| (replies.into_iter().next().unwrap_or_default(), action))
+                .map_err(TvError::from)
+        })
+        .await?;
+        // the command most likely changed some of them
+        self.poll_now.store(true, Ordering::Relaxed);
+
+        match reply.as_str() {
+            "OK" => Ok(()),
+            reply => Err(TvError::InvalidRequest(format!(
+                "The TV answered {} to {} {}",
+                reply, command_name, action
+            ))),
+        }
+    }
+
+    fn state_of(&self, property: &str) -> Option<String> {
+        self.state.lock().unwrap().get(property).cloned()
+    }
+}
+
+#[interface(name = "org.lgster.Tv")]
+impl TvObject {
+    /// Sends the wake-on-lan packet (the MAC address must be known, see lgster-wake)
+    async fn wake(&self) -> Result<(), TvError> {
+        self.run_ok("power", "on", vec![]).await
+    }
+
+    async fn power_off(&self) -> Result<(), TvError> {
+        self.run_ok("power", "off", vec![]).await
+    }
+
+    async fn set_volume(&self, volume: u32) -> Result<(), TvError> {
+        self.run_ok("set", "volume", vec![volume.to_string()]).await
+    }
+
+    async fn set_mute(&self, muted: bool) -> Result<(), TvError> {
+        let muted = if muted { "on" } else { "off" };
+        self.run_ok("set", "mute", vec![muted.to_string()]).await
+    }
+
+    /// An input of `lgster input` (e.g. `hdmi-1`)
+    async fn set_input(&self, input: String) -> Result<(), TvError> {
+        self.run_ok("input", &input, vec![]).await
+    }
+
+    /// An app by its friendly name (e.g. `netflix`) or its id
+    async fn launch_app(&self, app: String) -> Result<(), TvError> {
+        self.run_ok("app", "launch", vec![app]).await
+    }
+
+    /// A key of `lgster key` (e.g. `ok` or `vol-up`)
+    async fn press_key(&self, key: String) -> Result<(), TvError> {
+        self.run_ok("key", &key, vec![]).await
+    }
+
+    /// The TV profile or host it was given by
+    #[zbus(property)]
+    fn name(&self) -> String {
+        self.target.name.clone()
+    }
+
+    #[zbus(property)]
+    fn host(&self) -> String {
+        self.target.host.clone()
+    }
+
+    /// Whether it answered the last poll (it doesn't while it's off)
+    #[zbus(property)]
+    fn reachable(&self) -> bool {
+        self.state_of(REACHABLE).as_deref() == Some("true")
+    }
+
+    /// 0 until it's known
+    #[zbus(property)]
+    fn volume(&self) -> u32 {
+        self.state_of("volume").and_then(|volume| volume.parse().ok()).unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    fn muted(&self) -> bool {
+        self.state_of("mute").as_deref() == Some("on")
+    }
+
+    /// The friendly name of the app (e.g. `netflix`) or, when there's none, its id.
+    /// Empty until it's known.
+    #[zbus(property)]
+    fn current_app(&self) -> String {
+        self.state_of("current-app").unwrap_or_default()
+    }
+}
+
+/// The object path of a TV, which can only have letters, digits and underscores
+fn object_path(target: &Target) -> String {
+    let name: String = target
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}/{}", DBUS_PATH, name)
+}
+
+/// A watched property as the D-Bus property it is, if it's one
+fn dbus_property(property: &str, value: &str) -> Option<(&'static str, Value<'static>)> {
+    match property {
+        REACHABLE => Some(("Reachable", Value::from(value == "true"))),
+        "volume" => Some(("Volume", Value::from(value.parse::<u32>().unwrap_or_default()))),
+        "mute" => Some(("Muted", Value::from(value == "on"))),
+        "current-app" => Some(("CurrentApp", Value::from(value.to_string()))),
+        _ => None,
+    }
+}
+
+/// Serves the TVs on D-Bus as `org.lgster.Tv`, each one an object with the methods to control it
+/// and properties that are polled every `interval`, with a PropertiesChanged signal whenever one
+/// of them changes. It only returns when the connection to the bus is lost.
+pub fn run_dbus(options: &DbusOptions, targets: &[Target], config: Config, commands: &[Command]) -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(config);
+    let shared_commands = Arc::new(commands.to_vec());
+    let poll_now = Arc::new(AtomicBool::new(true));
+    let states: Vec<_> = targets.iter().map(|_| Arc::new(Mutex::new(BTreeMap::new()))).collect();
+
+    let builder = match options.bus {
+        Bus::Session => Builder::session()?,
+        Bus::System => Builder::system()?,
+    };
+    let mut builder = builder.name(DBUS_NAME)?.serve_at(DBUS_PATH, fdo::ObjectManager)?;
+    for (target, state) in targets.iter().zip(&states) {
+        let tv = TvObject {
+            target: target.clone(),
+            config: config.clone(),
+            commands: shared_commands.clone(),
+            state: state.clone(),
+            poll_now: poll_now.clone(),
+        };
+        builder = builder.serve_at(object_path(target), tv)?;
+    }
+    let connection = builder.build()?;
+
+    // the catalog always has the queries
+    let query = commands.iter().find(|command| command.command_name == "query").unwrap();
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+    let watchers: Vec<Mutex<Watcher>> = targets
+        .iter()
+        .map(|target| Mutex::new(Watcher::new(target, query, &config, &app_catalog, options.interval, &BTreeMap::new())))
+        .collect();
+
+    loop {
+        if poll_now.swap(false, Ordering::Relaxed) {
+            watchers.iter().for_each(|watcher| watcher.lock().unwrap().poll_everything_now());
+        }
+
+        let now = Instant::now();
+        if watchers.iter().any(|watcher| watcher.lock().unwrap().next_poll() <= now) {
+            let changes = run_concurrently(&watchers, options.concurrency, |watcher| watcher.lock().unwrap().poll_due());
+            for (((target, watcher), state), changes) in targets.iter().zip(&watchers).zip(&states).zip(changes) {
+                if changes.is_empty() {
+                    continue;
+                }
+                *state.lock().unwrap() = watcher.lock().unwrap().state().clone();
+
+                let changed: HashMap<&str, Value> = changes
+                    .iter()
+                    .filter_map(|change| dbus_property(&change.property, &change.new))
+                    .collect();
+                connection.emit_signal(
+                    None::<BusName>,
+                    object_path(target),
+                    "org.freedesktop.DBus.Properties",
+                    "PropertiesChanged",
+                    &(DBUS_NAME, changed, Vec::<&str>::new()),
+                )?;
+            }
+        }
+
+        sleep(Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,362 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::arp::mac_address_of;
+use crate::fanout::run_concurrently;
+use crate::network::send_http_request;
+
+const SSDP_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+// LG TVs answer to both, but the second one is the only one that's LG specific
+const SSDP_SEARCH_TARGETS: &[&str] = &[
+    "urn:schemas-upnp-org:device:MediaRenderer:1",
+    "urn:lge-com:service:webos-second-screen:1",
+];
+// scanning anything bigger than a /16 is most certainly a mistake
+const MAX_SCAN_PREFIX_SIZE: u32 = 16;
+
+#[derive(Debug)]
+pub struct InvalidCidrError {
+    cidr: String,
+}
+
+impl fmt::Display for InvalidCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid range {} (should be an IPv4 CIDR from /{} to /32, e.g. 192.168.0.0/24)",
+            self.cidr, MAX_SCAN_PREFIX_SIZE
+        )
+    }
+}
+
+impl Error for InvalidCidrError {}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredTv {
+    pub ip: Ipv4Addr,
+    pub friendly_name: Option<String>,
+    pub model_name: Option<String>,
+    pub mac_address: Option<String>,
+    /// Whether the IP Control port answered
+    pub ip_control: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoverOptions {
+    /// Whether to look for TVs with SSDP (UPnP) at all
+    pub ssdp: bool,
+    /// How long to wait for SSDP answers and for each probe and description request
+    pub timeout: Duration,
+    /// Addresses to probe on the IP Control port, to find TVs that don't answer to SSDP
+    pub scan: Vec<Ipv4Addr>,
+    pub port: u16,
+    pub concurrency: usize,
+}
+
+/// Expands an IPv4 CIDR (e.g. 192.168.0.0/24) into its host addresses
+pub fn parse_ipv4_cidr(cidr: &str) -> Result<Vec<Ipv4Addr>, InvalidCidrError> {
+    let invalid = || InvalidCidrError {
+        cidr: cidr.to_string(),
+    };
+
+    let (address, prefix) = match cidr.find('/') {
+        Some(i) => (&cidr[..i], &cidr[i + 1..]),
+        None => (cidr, "32"),
+    };
+    let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+    // parse() would also take e.g. +24
+    if !prefix.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let prefix: u32 = prefix.parse().map_err(|_| invalid())?;
+    if !(MAX_SCAN_PREFIX_SIZE..=32).contains(&prefix) {
+        return Err(invalid());
+    }
+
+    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+    let network = u32::from(address) & mask;
+    let broadcast = network | !mask;
+    // /31 and /32 don't have a network and a broadcast address to skip
+    let hosts = match prefix {
+        31 | 32 => network..=broadcast,
+        _ => network + 1..=broadcast - 1,
+    };
+
+    Ok(hosts.map(Ipv4Addr::from).collect())
+}
+
+/// Finds LG TVs on the local network with SSDP and/or by probing the IP Control port
+pub fn discover(options: &DiscoverOptions) -> io::Result<Vec<DiscoveredTv>> {
+    let mut tvs: BTreeMap<Ipv4Addr, DiscoveredTv> = BTreeMap::new();
+
+    if options.ssdp {
+        let answers = ssdp_search(options.timeout)?;
+        let descriptions = run_concurrently(&answers, options.concurrency, |answer| {
+            answer
+                .location
+                .as_ref()
+                .and_then(|location| fetch_description(location, options.timeout))
+        });
+
+        for (answer, description) in answers.into_iter().zip(descriptions) {
+            let description = description.unwrap_or_default();
+            let is_lg = answer.server.to_lowercase().contains("webos")
+                || answer.server.contains("LGE")
+                || description.manufacturer.as_deref().unwrap_or("").contains("LG");
+            if !is_lg {
+                continue;
+            }
+
+            tvs.insert(
+                answer.ip,
+                DiscoveredTv {
+                    ip: answer.ip,
+                    friendly_name: description.friendly_name,
+                    model_name: description.model_name,
+                    mac_address: None,
+                    ip_control: false,
+                },
+            );
+        }
+    }
+
+    let mut to_probe: Vec<Ipv4Addr> = tvs.keys().copied().collect();
+    to_probe.extend(options.scan.iter().filter(|ip| !tvs.contains_key(ip)));
+    let probes = run_concurrently(&to_probe, options.concurrency, |ip| {
+        let socket_address = SocketAddr::from((*ip, options.port));
+        TcpStream::connect_timeout(&socket_address, options.timeout).is_ok()
+    });
+
+    for (ip, answered) in to_probe.into_iter().zip(probes) {
+        match tvs.get_mut(&ip) {
+            Some(tv) => tv.ip_control = answered,
+            None if answered => {
+                tvs.insert(
+                    ip,
+                    DiscoveredTv {
+                        ip,
+                        friendly_name: None,
+                        model_name: None,
+                        mac_address: None,
+                        ip_control: true,
+                    },
+                );
+            }
+            None => {}
+        }
+    }
+
+    // reading the ARP table is only meaningful after we've talked to them
+    for tv in tvs.values_mut() {
+        tv.mac_address = mac_address_of(tv.ip);
+    }
+
+    Ok(tvs.into_values().collect())
+}
+
+#[derive(Debug)]
+struct SsdpAnswer {
+    ip: Ipv4Addr,
+    location: Option<String>,
+    server: String,
+}
+
+#[derive(Debug, Default)]
+struct DeviceDescription {
+    friendly_name: Option<String>,
+    model_name: Option<String>,
+    manufacturer: Option<String>,
+}
+
+fn ssdp_search(timeout: Duration) -> io::Result<Vec<SsdpAnswer>> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_multicast_ttl_v4(2)?;
+
+    for search_target in SSDP_SEARCH_TARGETS {
+        let message = format!(
+            concat!(
+                "M-SEARCH * HTTP/1.1\r\n",
+                "HOST: 239.255.255.250:1900\r\n",
+                "MAN: \"ssdp:discover\"\r\n",
+                "MX: {}\r\n",
+                "ST: {}\r\n",
+                "\r\n"
+            ),
+            timeout.as_secs().clamp(1, 5),
+            search_target
+        );
+        socket.send_to(message.as_bytes(), SSDP_ADDRESS)?;
+    }
+
+    let mut answers: Vec<SsdpAnswer> = vec![];
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 2048];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.as_millis() == 0 {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (size, sender) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            // NOTE: see the note on network::send_and_receive_tcp_message about these two
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => return Err(e),
+        };
+        let ip = match sender {
+            SocketAddr::V4(sender) => *sender.ip(),
+            SocketAddr::V6(_) => continue,
+        };
+        if answers.iter().any(|answer| answer.ip == ip) {
+            continue;
+        }
+
+        let headers = String::from_utf8_lossy(&buffer[..size]);
+        answers.push(SsdpAnswer {
+            ip,
+            location: header_value(&headers, "location"),
+            server: header_value(&headers, "server").unwrap_or_default(),
+        });
+    }
+
+    Ok(answers)
+}
+
+fn header_value(headers: &str, name: &str) -> Option<String> {
+    headers.lines().find_map(|line| {
+        let i = line.find(':')?;
+        if line[..i].trim().eq_ignore_ascii_case(name) {
+            Some(line[i + 1..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn fetch_description(location: &str, timeout: Duration) -> Option<DeviceDescription> {
+    let (status, body) = send_http_request("GET", location, None, timeout).ok()?;
+    if status != 200 {
+        return None;
+    }
+
+    let xml = String::from_utf8_lossy(&body);
+    Some(DeviceDescription {
+        friendly_name: xml_tag_value(&xml, "friendlyName"),
+        model_name: xml_tag_value(&xml, "modelName"),
+        manufacturer: xml_tag_value(&xml, "manufacturer"),
+    })
+}
+
+// the descriptions are simple enough that pulling a whole XML parser for them isn't worth it
+fn xml_tag_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+
+    let value = xml[start..end].trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(
+        value
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(cidr: &str) -> Vec<String> {
+        parse_ipv4_cidr(cidr).unwrap().iter().map(|host| host.to_string()).collect()
+    }
+
+    #[test]
+    fn expands_cidrs() {
+        assert_eq!(hosts("192.168.0.10/32"), ["192.168.0.10"]);
+        assert_eq!(hosts("192.168.0.10"), ["192.168.0.10"]);
+        assert_eq!(hosts("192.168.0.10/31"), ["192.168.0.10", "192.168.0.11"]);
+        assert_eq!(hosts("192.168.0.8/30"), ["192.168.0.9", "192.168.0.10"]);
+
+        let network = hosts("10.1.2.0/24");
+        assert_eq!(network.len(), 254);
+        assert_eq!(network.first().unwrap(), "10.1.2.1");
+        assert_eq!(network.last().unwrap(), "10.1.2.254");
+
+        let network = hosts("10.1.0.0/16");
+        assert_eq!(network.len(), 65_534);
+        assert_eq!(network.last().unwrap(), "10.1.255.254");
+    }
+
+    #[test]
+    fn ignores_the_host_bits() {
+        assert_eq!(hosts("192.168.0.77/24"), hosts("192.168.0.0/24"));
+        assert_eq!(hosts("192.168.0.11/31"), ["192.168.0.10", "192.168.0.11"]);
+    }
+
+    #[test]
+    fn rejects_invalid_cidrs() {
+        for cidr in [
+            "192.168.0.0/0",
+            "192.168.0.0/8",
+            "192.168.0.0/15",
+            "192.168.0.0/33",
+            "192.168.0.0/",
+            "192.168.0.0/+24",
+            "192.168.0.0/ 24",
+            "192.168.0.0/24/24",
+            "192.168.0.0/-1",
+            "192.168.0/24",
+            "192.168.0.256/24",
+            "tv/24",
+            "",
+        ] {
+            assert!(parse_ipv4_cidr(cidr).is_err(), "{}", cidr);
+        }
+    }
+
+    #[test]
+    fn reads_tag_values() {
+        let xml = "<root><device><friendlyName> [LG] webOS TV </friendlyName>\
+                   <manufacturer>LG Electronics</manufacturer></device></root>";
+        assert_eq!(xml_tag_value(xml, "friendlyName").as_deref(), Some("[LG] webOS TV"));
+        assert_eq!(xml_tag_value(xml, "manufacturer").as_deref(), Some("LG Electronics"));
+        assert_eq!(xml_tag_value(xml, "modelName"), None);
+        let device = "<friendlyName> [LG] webOS TV </friendlyName><manufacturer>LG Electronics</manufacturer>";
+        assert_eq!(xml_tag_value(xml, "device").as_deref(), Some(device));
+    }
+
+    #[test]
+    fn reads_the_first_of_nested_devices() {
+        let xml = "<device><friendlyName>TV</friendlyName><deviceList>\
+                   <device><friendlyName>Renderer</friendlyName></device></deviceList></device>";
+        assert_eq!(xml_tag_value(xml, "friendlyName").as_deref(), Some("TV"));
+    }
+
+    #[test]
+    fn unescapes_tag_values() {
+        let xml = "<friendlyName>&lt;Tom &amp; Jerry&apos;s &quot;TV&quot;&gt; &amp;lt;</friendlyName>";
+        assert_eq!(xml_tag_value(xml, "friendlyName").as_deref(), Some("<Tom & Jerry's \"TV\"> &lt;"));
+    }
+
+    #[test]
+    fn skips_empty_and_unclosed_tags() {
+        assert_eq!(xml_tag_value("<modelName/>", "modelName"), None);
+        assert_eq!(xml_tag_value("<modelName />", "modelName"), None);
+        assert_eq!(xml_tag_value("<modelName></modelName>", "modelName"), None);
+        assert_eq!(xml_tag_value("<modelName>  </modelName>", "modelName"), None);
+        assert_eq!(xml_tag_value("<modelName>OLED", "modelName"), None);
+        assert_eq!(xml_tag_value("<modelName/><modelName>OLED</modelName>", "modelName").as_deref(), Some("OLED"));
+        assert_eq!(xml_tag_value("<modelNumber>55</modelNumber>", "modelName"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+use std::num::{ParseFloatError, ParseIntError};
+use std::time::TryFromFloatSecsError;
+
+use crate::actions::{UnknownActionError, UsageError};
+use crate::bytes::{InvalidByteSequenceError, WrongByteSequenceSize};
+use crate::channel::InvalidChannelError;
+use crate::config::ConfigError;
+use crate::crypto::ProtocolError;
+use crate::target::{MissingKeycodeError, UnknownGroupError};
+use crate::wake::{UnknownMacAddressError, WakeTimeoutError};
+
+/// The broad kind of an error, for the frontends that need to tell them apart
+/// (e.g. to pick an HTTP status code) without knowing every error type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    /// The command or its parameters are wrong
+    InvalidRequest,
+    /// There's no such command, action, TV profile or group
+    NotFound,
+    /// The request didn't bring the right credentials (only for the servers)
+    Unauthorized,
+    /// The TV profile lacks something the command needs (e.g. a keycode or a MAC address)
+    NotConfigured,
+    /// The TV couldn't be reached
+    Unreachable,
+    /// The TV didn't answer in time
+    Timeout,
+    /// Anything else
+    Internal,
+}
+
+impl ErrorClass {
+    /// Classifies an error by its type, looking into its sources when the type itself isn't known
+    pub fn of(error: &(dyn Error + 'static)) -> ErrorClass {
+        let class = if error.is::<UsageError>()
+            || error.is::<InvalidChannelError>()
+            || error.is::<ParseIntError>()
+            || error.is::<ParseFloatError>()
+            || error.is::<TryFromFloatSecsError>()
+            || is_json_error(error)
+            || error.is::<InvalidByteSequenceError>()
+            || error.is::<WrongByteSequenceSize>()
+        {
+            Some(ErrorClass::InvalidRequest)
+        } else if error.is::<UnknownActionError>() || error.is::<UnknownGroupError>() {
+            Some(ErrorClass::NotFound)
+        } else if error.is::<MissingKeycodeError>() || error.is::<UnknownMacAddressError>() {
+            Some(ErrorClass::NotConfigured)
+        } else if let Some(e) = error.downcast_ref::<ProtocolError>() {
+            // a reply that doesn't decrypt usually comes from a TV with another keycode
+            match e {
+                ProtocolError::InvalidLength | ProtocolError::NotACommand => Some(ErrorClass::NotConfigured),
+                ProtocolError::BufferTooSmall => Some(ErrorClass::Internal),
+            }
+        } else if error.is::<WakeTimeoutError>() {
+            Some(ErrorClass::Timeout)
+        } else if error.is::<ConfigError>() {
+            // its source is usually an io::Error that has nothing to do with the TV
+            Some(ErrorClass::Internal)
+        } else {
+            error.downcast_ref::<io::Error>().map(|e| match e.kind() {
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => ErrorClass::Timeout,
+                ErrorKind::InvalidInput => ErrorClass::InvalidRequest,
+                _ => ErrorClass::Unreachable,
+            })
+        };
+
+        match (class, error.source()) {
+            (Some(class), _) => class,
+            (None, Some(source)) => ErrorClass::of(source),
+            (None, None) => ErrorClass::Internal,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorClass::InvalidRequest => "invalid-request",
+            ErrorClass::NotFound => "not-found",
+            ErrorClass::Unauthorized => "unauthorized",
+            ErrorClass::NotConfigured => "not-configured",
+            ErrorClass::Unreachable => "unreachable",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Internal => "internal",
+        }
+    }
+}
+
+/// A request that isn't valid JSON (e.g. to the REST API)
+#[cfg(feature = "serde_json")]
+fn is_json_error(error: &(dyn Error + 'static)) -> bool {
+    error.is::<serde_json::Error>()
+}
+
+// without serde_json there's no JSON to get wrong
+#[cfg(not(feature = "serde_json"))]
+fn is_json_error(_error: &(dyn Error + 'static)) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_in_another_keycode_are_a_configuration_error() {
+        assert_eq!(ErrorClass::of(&ProtocolError::NotACommand), ErrorClass::NotConfigured);
+        assert_eq!(ErrorClass::of(&ProtocolError::InvalidLength), ErrorClass::NotConfigured);
+        let boxed: Box<dyn Error> = Box::new(ProtocolError::InvalidLength);
+        assert_eq!(ErrorClass::of(boxed.as_ref()), ErrorClass::NotConfigured);
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Runs `job` for every item with at most `limit` of them running at the same time,
+/// returning the results in the same order as the items.
+pub fn run_concurrently<T, R, F>(items: &[T], limit: usize, job: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next_item = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..limit.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next_item.fetch_add(1, Ordering::SeqCst);
+                if i >= items.len() {
+                    break;
+                }
+
+                let result = job(&items[i]);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Every item should have been processed"))
+        .collect()
+}
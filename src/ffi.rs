@@ -0,0 +1,225 @@
+//! The C API (see include/lgster.h), for programs that would otherwise shell out to lgster.
+//! Every function that can fail returns an `LgsterStatus`, and the message of the last error
+//! of the calling thread can be read with `lgster_last_error`.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::io::{Error as IOError, ErrorKind};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::bytes::Salt;
+use crate::comm::{send_wol_packet, WakeOptions};
+use crate::error::ErrorClass;
+use crate::target::{Target, DEFAULT_SALT};
+use crate::wake::DEFAULT_WAKE_TARGET;
+
+/// How a call went. Every error is one of the classes of `ErrorClass`, and the values won't change.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LgsterStatus {
+    Ok = 0,
+    /// The command or the arguments are wrong (e.g. a null pointer or an invalid salt)
+    InvalidRequest = 1,
+    NotFound = 2,
+    Unauthorized = 3,
+    /// Something the call needs is missing (e.g. the client has no keycode)
+    NotConfigured = 4,
+    /// The TV couldn't be reached
+    Unreachable = 5,
+    /// The TV didn't answer in time
+    Timeout = 6,
+    Internal = 7,
+}
+
+impl From<ErrorClass> for LgsterStatus {
+    fn from(class: ErrorClass) -> Self {
+        match class {
+            ErrorClass::InvalidRequest => LgsterStatus::InvalidRequest,
+            ErrorClass::NotFound => LgsterStatus::NotFound,
+            ErrorClass::Unauthorized => LgsterStatus::Unauthorized,
+            ErrorClass::NotConfigured => LgsterStatus::NotConfigured,
+            ErrorClass::Unreachable => LgsterStatus::Unreachable,
+            ErrorClass::Timeout => LgsterStatus::Timeout,
+            ErrorClass::Internal => LgsterStatus::Internal,
+        }
+    }
+}
+
+/// A TV to send commands to
+pub struct LgsterClient {
+    target: Target,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: Option<String>) {
+    // the messages can't have a nul in the middle, but just in case
+    let message = message.map(|message| CString::new(message.replace('\0', " ")).unwrap());
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+/// Runs the body of a call, turning its error (or panic, which can't cross into C) into a status
+fn run(call: impl FnOnce() -> Result<(), Box<dyn Error>>) -> LgsterStatus {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(())) => {
+            set_last_error(None);
+            LgsterStatus::Ok
+        }
+        Ok(Err(e)) => {
+            set_last_error(Some(e.to_string()));
+            ErrorClass::of(e.as_ref()).into()
+        }
+        Err(_) => {
+            set_last_error(Some("lgster panicked (this is a bug)".to_string()));
+            LgsterStatus::Internal
+        }
+    }
+}
+
+/// A string argument, which can only be null when it's optional
+unsafe fn string_argument<'a>(name: &str, pointer: *const c_char) -> Result<Option<&'a str>, Box<dyn Error>> {
+    if pointer.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(pointer).to_str() {
+        Ok(string) => Ok(Some(string)),
+        Err(_) => Err(Box::new(IOError::new(
+            ErrorKind::InvalidInput,
+            format!("{} isn't valid UTF-8", name),
+        ))),
+    }
+}
+
+unsafe fn required_string_argument<'a>(name: &str, pointer: *const c_char) -> Result<&'a str, Box<dyn Error>> {
+    string_argument(name, pointer)?
+        .ok_or_else(|| IOError::new(ErrorKind::InvalidInput, format!("{} can't be null", name)).into())
+}
+
+fn null_argument(name: &str) -> Box<dyn Error> {
+    Box::new(IOError::new(ErrorKind::InvalidInput, format!("{} can't be null", name)))
+}
+
+/// Creates a client for the TV at `host` and `port` (usually 9761), storing it in `*client`
+/// (or null when it fails). The `keycode` can be null, in which case every command fails with
+/// `LGSTER_STATUS_NOT_CONFIGURED`, and so can the `salt` (hyphen-separated hex bytes), in which
+/// case the usual one is used. The client must be freed with `lgster_client_free`.
+///
+/// # Safety
+///
+/// The strings must be null or valid nul-terminated strings, and `client` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lgster_client_new(
+    host: *const c_char,
+    port: u16,
+    keycode: *const c_char,
+    salt: *const c_char,
+    client: *mut *mut LgsterClient,
+) -> LgsterStatus {
+    run(|| {
+        if client.is_null() {
+            return Err(null_argument("client"));
+        }
+        *client = ptr::null_mut();
+
+        let host = required_string_argument("host", host)?;
+        let keycode = string_argument("keycode", keycode)?;
+        let salt = match string_argument("salt", salt)? {
+            Some(salt) => salt.parse::<Salt>()?.0,
+            None => DEFAULT_SALT,
+        };
+
+        let target = Target {
+            name: host.to_string(),
+            profile: None,
+            host: host.to_string(),
+            port,
+            keycode: keycode.map(str::to_string),
+            salt,
+        };
+        *client = Box::into_raw(Box::new(LgsterClient { target }));
+        Ok(())
+    })
+}
+
+/// Frees a client made by `lgster_client_new` (doing nothing for null)
+///
+/// # Safety
+///
+/// The client must be null or one made by `lgster_client_new` that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lgster_client_free(client: *mut LgsterClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Sends an IP Control command (e.g. `VOLUME_CONTROL 10`) to the TV and stores its reply
+/// (e.g. `OK`, without the trailing line feed) in `*reply`, or null when it fails. The reply
+/// must be freed with `lgster_string_free`.
+///
+/// # Safety
+///
+/// The client must be one made by `lgster_client_new`, `command` a valid nul-terminated
+/// string and `reply` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lgster_send(
+    client: *const LgsterClient,
+    command: *const c_char,
+    reply: *mut *mut c_char,
+) -> LgsterStatus {
+    run(|| {
+        if reply.is_null() {
+            return Err(null_argument("reply"));
+        }
+        *reply = ptr::null_mut();
+
+        let client = client.as_ref().ok_or_else(|| null_argument("client"))?;
+        let command = required_string_argument("command", command)?;
+        let response = client.target.send(command.to_string())?;
+        *reply = CString::new(response.trim_end().replace('\0', ""))?.into_raw();
+        Ok(())
+    })
+}
+
+/// Sends the wake-on-lan packet for `mac_address` (e.g. `a8:23:fe:01:02:03`) to `target`, which
+/// is usually a broadcast address (and 255.255.255.255 when it's null)
+///
+/// # Safety
+///
+/// The strings must be null (only `target`) or valid nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn lgster_wake(mac_address: *const c_char, target: *const c_char) -> LgsterStatus {
+    run(|| {
+        let mac_address = required_string_argument("mac_address", mac_address)?;
+        let target = string_argument("target", target)?.unwrap_or(DEFAULT_WAKE_TARGET);
+        send_wol_packet(target, mac_address, &WakeOptions::default())?;
+        Ok(())
+    })
+}
+
+/// Frees a string returned by lgster (doing nothing for null)
+///
+/// # Safety
+///
+/// The string must be null or one returned by lgster that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lgster_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The message of the last error of the calling thread, or null if its last call succeeded.
+/// It belongs to lgster and is only valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn lgster_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::io::{self, Error as IOError, ErrorKind};
+use std::process::{Command as Process, Stdio};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use crate::config::HookSettings;
+use crate::network::send_http_request;
+use crate::target::Target;
+use crate::watch::StateChange;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Makes sure every hook does exactly one thing, before any of them is needed
+pub fn check_hooks(hooks: &[HookSettings]) -> Result<(), Box<dyn Error>> {
+    for hook in hooks {
+        if hook.run.is_some() == hook.url.is_some() {
+            return Err(format!("The hook {} needs either run or url (and not both)", hook_name(hook)).into());
+        }
+        if let Some(url) = &hook.url {
+            if !url.starts_with("http://") {
+                return Err(format!("The url of the hook {} must be http://", hook_name(hook)).into());
+            }
+        }
+        for (setting, seconds) in [("debounce", hook.debounce), ("timeout", hook.timeout)] {
+            if seconds.is_some_and(|seconds| Instant::now().checked_add(Duration::from_secs(seconds)).is_none()) {
+                return Err(format!("The {} of the hook {} is too long", setting, hook_name(hook)).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+struct PendingHook {
+    hook: usize,
+    change: StateChange,
+    due: Instant,
+}
+
+/// Runs the hooks of a TV for the changes in its state, once each change has lasted long enough
+pub struct Hooks<'a> {
+    hooks: Vec<&'a HookSettings>,
+    pending: Vec<PendingHook>,
+}
+
+impl<'a> Hooks<'a> {
+    /// The hooks that are for `target`
+    pub fn new(hooks: &'a [HookSettings], target: &Target) -> Self {
+        let hooks = hooks
+            .iter()
+            .filter(|hook| {
+                hook.tvs.is_empty()
+                    || hook.tvs.iter().any(|tv| {
+                        *tv == target.name || *tv == target.host || Some(tv) == target.profile.as_ref()
+                    })
+            })
+            .collect();
+
+        Hooks { hooks, pending: vec![] }
+    }
+
+    /// Takes a change into account. The first value of a property (a change from nothing) doesn't
+    /// run anything, since it's what the TV was already doing.
+    pub fn handle(&mut self, change: &StateChange) {
+        let old = match &change.old {
+            Some(old) => old,
+            None => return,
+        };
+
+        for (i, hook) in self.hooks.iter().enumerate() {
+            if hook.property != change.property {
+                continue;
+            }
+            // whatever was waiting didn't last long enough
+            self.pending.retain(|pending| pending.hook != i);
+
+            if !value_matches(&hook.from, old) || !value_matches(&hook.to, &change.new) {
+                continue;
+            }
+            // check_hooks doesn't let through a debounce that's too long to ever be over
+            if let Some(due) = Instant::now().checked_add(Duration::from_secs(hook.debounce.unwrap_or(0))) {
+                self.pending.push(PendingHook {
+                    hook: i,
+                    change: change.clone(),
+                    due,
+                });
+            }
+        }
+    }
+
+    /// Runs the hooks that are due, each one in a thread of its own so that slow ones don't hold up
+    /// the polling. Failures are only reported on stderr.
+    pub fn run_due(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = self.pending.drain(..).partition(|pending| pending.due <= now);
+        self.pending = pending;
+
+        for PendingHook { hook, change, .. } in due {
+            let hook = self.hooks[hook].clone();
+            thread::spawn(move || {
+                if let Err(e) = run_hook(&hook, &change) {
+                    eprintln!("The hook {} for {} failed: {}", hook_name(&hook), change.tv, e);
+                }
+            });
+        }
+    }
+
+    /// When the next hook is due, if any is waiting
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|pending| pending.due).min()
+    }
+}
+
+/// Either the exact value or, ending with `*`, a prefix of it, where no pattern matches anything
+fn value_matches(pattern: &Option<String>, value: &str) -> bool {
+    match pattern {
+        None => true,
+        Some(pattern) => match pattern.strip_suffix('*') {
+            Some(prefix) => value.starts_with(prefix),
+            None => pattern == value,
+        },
+    }
+}
+
+fn hook_name(hook: &HookSettings) -> &str {
+    hook.name.as_deref().unwrap_or(&hook.property)
+}
+
+fn run_hook(hook: &HookSettings, change: &StateChange) -> Result<(), Box<dyn Error>> {
+    let timeout = hook.timeout.map(Duration::from_secs).unwrap_or(DEFAULT_TIMEOUT);
+
+    if let Some(url) = &hook.url {
+        let body = serde_json::to_vec(change)?;
+        let (status, _) = send_http_request("POST", url, Some(("application/json", &body)), timeout)?;
+        if !(200..300).contains(&status) {
+            return Err(format!("{} answered with {}", url, status).into());
+        }
+    }
+    if let Some(command) = &hook.run {
+        run_shell_command(command, change, timeout)?;
+    }
+    Ok(())
+}
+
+fn run_shell_command(command: &str, change: &StateChange, timeout: Duration) -> io::Result<()> {
+    #[cfg(unix)]
+    let mut process = Process::new("sh");
+    #[cfg(unix)]
+    process.arg("-c");
+    #[cfg(not(unix))]
+    let mut process = Process::new("cmd");
+    #[cfg(not(unix))]
+    process.arg("/C");
+
+    let mut child = process
+        .arg(command)
+        .env("LGSTER_TV", &change.tv)
+        .env("LGSTER_PROPERTY", &change.property)
+        .env("LGSTER_OLD", change.old.as_deref().unwrap_or(""))
+        .env("LGSTER_NEW", &change.new)
+        .env("LGSTER_TIMESTAMP", change.timestamp.to_string())
+        .stdin(Stdio::null())
+        // stdout is where the changes are reported
+        .stdout(io::stderr())
+        .spawn()?;
+
+    // no deadline for one that's too far away to be reached
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return match status.success() {
+                true => Ok(()),
+                false => Err(IOError::other(format!("`{}` exited with {}", command, status))),
+            };
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(IOError::new(
+                ErrorKind::TimedOut,
+                format!("`{}` took more than {}s", command, timeout.as_secs()),
+            ));
+        }
+        sleep(Duration::from_millis(50));
+    }
+}
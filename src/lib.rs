@@ -1,4 +1,45 @@
+pub mod actions;
+pub mod apps;
+pub mod arp;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+pub mod bytes;
+pub mod channel;
+#[cfg(feature = "cli")]
 pub mod cli;
 pub mod comm;
+pub mod commands;
+pub mod config;
 pub mod crypto;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "discover")]
+pub mod discover;
+pub mod error;
+pub mod fanout;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "watch")]
+pub mod hooks;
+pub mod logging;
+#[cfg(feature = "dbus")]
+pub mod logind;
+pub mod metrics;
+#[cfg(feature = "bridge")]
+pub mod mqtt;
 pub mod network;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "schedule")]
+pub mod schedule;
+pub mod target;
+pub mod transport;
+pub mod wake;
+#[cfg(feature = "watch")]
+pub mod watch;
@@ -0,0 +1,69 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static REVEAL_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Writes the log records to stderr, each one with how long after the start of the process it happened
+struct StderrLogger {
+    started_at: OnceLock<Instant>,
+}
+
+static LOGGER: StderrLogger = StderrLogger {
+    started_at: OnceLock::new(),
+};
+
+impl Log for StderrLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let elapsed = self.started_at.get_or_init(Instant::now).elapsed();
+        // one write per record, so that the records of different threads don't get mixed up
+        let line = format!(
+            "[{:>8.3}s {:<5} {}] {}\n",
+            elapsed.as_secs_f64(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let _ = std::io::stderr().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs everything up to `level` to stderr, with the secrets (keycodes, keys and passwords)
+/// redacted unless `reveal_secrets`. Only the first call does anything.
+pub fn init_logging(level: LevelFilter, reveal_secrets: bool) {
+    LOGGER.started_at.get_or_init(Instant::now);
+    REVEAL_SECRETS.store(reveal_secrets, Ordering::Relaxed);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Something that is only shown in the logs when the secrets are revealed
+pub struct Secret<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match REVEAL_SECRETS.load(Ordering::Relaxed) {
+            true => self.0.fmt(f),
+            false => write!(f, "<redacted>"),
+        }
+    }
+}
+
+/// Bytes as lowercase hex, for the logs
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
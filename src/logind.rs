@@ -0,0 +1,170 @@
+use log::debug;
+use std::error::Error;
+use std::io::Error as IOError;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::message::Type as MessageType;
+use zbus::zvariant::OwnedFd;
+use zbus::{proxy, MatchRule};
+
+use crate::actions::{run_action, UnknownActionError};
+use crate::apps::AppCatalog;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::fanout::run_concurrently;
+use crate::target::Target;
+
+const LOGIND_NAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+// the network is usually not back up the moment the computer resumes
+const WAKE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    /// Takes an inhibitor lock, which is held until the returned descriptor is closed
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SleepOptions {
+    /// The input the TVs without one in their profile are switched to on resume
+    pub input: Option<String>,
+    /// How long a TV is given to wake up on resume
+    pub wait: Duration,
+    pub concurrency: usize,
+}
+
+/// Turns the TVs off before the computer suspends or shuts down, and on resume wakes them up and
+/// switches them to their input. logind is asked to hold the suspend and the shutdown until the
+/// TVs were turned off (for at most its InhibitDelayMaxSec). It only returns when the connection
+/// to the system bus is lost.
+pub fn follow_sleep(
+    options: &SleepOptions,
+    targets: &[Target],
+    config: &Config,
+    commands: &[Command],
+) -> Result<(), Box<dyn Error>> {
+    // the catalog always has these commands
+    let find = |name: &str| commands.iter().find(|command| command.command_name == name).unwrap();
+    let (power, input) = (find("power"), find("input"));
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+
+    // a typo in an input should show now instead of on the next resume
+    let inputs: Vec<Option<String>> = targets.iter().map(|target| input_for(target, config, options)).collect();
+    for tv_input in inputs.iter().flatten() {
+        if !input.command_actions.iter().any(|(action, _)| action == tv_input) {
+            return Err(Box::new(UnknownActionError::new(input.command_name, tv_input)));
+        }
+    }
+
+    let connection = Connection::system()?;
+    let manager = ManagerProxyBlocking::new(&connection)?;
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(LOGIND_NAME)?
+        .path(LOGIND_PATH)?
+        .interface(MANAGER_INTERFACE)?
+        .build();
+    // subscribed before taking the lock, so that no signal is missed in between
+    let signals = MessageIterator::for_match_rule(rule, &connection, None)?;
+    let mut inhibitor = Some(take_inhibitor(&manager)?);
+
+    for message in signals {
+        let message = message?;
+        let header = message.header();
+        let event = match header.member().map(|member| member.as_str()) {
+            Some("PrepareForSleep") => "sleep",
+            Some("PrepareForShutdown") => "shutdown",
+            _ => continue,
+        };
+        let starting: bool = message.body().deserialize()?;
+        debug!("logind signalled event={} starting={}", event, starting);
+
+        if starting {
+            let report = run_concurrently(targets, options.concurrency, |target| {
+                describe(target, "power off", run_action(power, "off", &[], target, config, &app_catalog))
+            });
+            print_report(event, &report);
+            // closing it lets the computer go on
+            inhibitor = None;
+        } else {
+            // taken first, in case the computer goes back to sleep while the TVs wake up
+            if inhibitor.is_none() {
+                inhibitor = Some(take_inhibitor(&manager)?);
+            }
+            // a shutdown that was cancelled has nothing to resume
+            if event == "sleep" {
+                let tvs: Vec<_> = targets.iter().zip(&inputs).collect();
+                let report = run_concurrently(&tvs, options.concurrency, |(target, tv_input)| {
+                    resume(target, tv_input.as_deref(), power, input, config, &app_catalog, options.wait)
+                });
+                print_report("resume", &report.concat());
+            }
+        }
+    }
+    drop(inhibitor);
+    Err("Lost the connection to the system bus".into())
+}
+
+fn take_inhibitor(manager: &ManagerProxyBlocking) -> Result<OwnedFd, Box<dyn Error>> {
+    Ok(manager.inhibit("sleep:shutdown", "lgster", "Turning the TVs off", "delay")?)
+}
+
+/// The input of the TV profile or, if it has none, the one given on the command line
+fn input_for(target: &Target, config: &Config, options: &SleepOptions) -> Option<String> {
+    let profile_input = target.profile.as_ref().and_then(|profile| config.tvs[profile].input.clone());
+    profile_input.or_else(|| options.input.clone())
+}
+
+/// Wakes a TV up and switches it to its input, which it only gets when it woke up
+fn resume(
+    target: &Target,
+    tv_input: Option<&str>,
+    power: &Command,
+    input: &Command,
+    config: &Config,
+    app_catalog: &AppCatalog,
+    wait: Duration,
+) -> Vec<String> {
+    let deadline = Instant::now() + wait;
+    let woke_up_result = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let parameters = ["--wait".to_string(), remaining.as_secs_f64().to_string()];
+        match run_action(power, "on", &parameters, target, config, app_catalog) {
+            // e.g. the network is unreachable, for now
+            Err(e) if e.downcast_ref::<IOError>().is_some() && remaining > WAKE_RETRY_INTERVAL => {
+                debug!("couldn't wake up tv={} yet error={}", target.name, e);
+                sleep(WAKE_RETRY_INTERVAL);
+            }
+            result => break result,
+        }
+    };
+
+    let mut lines = vec![];
+    let woke_up = woke_up_result.is_ok();
+    lines.push(describe(target, "power on", woke_up_result));
+    if let (true, Some(tv_input)) = (woke_up, tv_input) {
+        let result = run_action(input, tv_input, &[], target, config, app_catalog);
+        lines.push(describe(target, &format!("input {}", tv_input), result));
+    }
+    lines
+}
+
+fn describe(target: &Target, step: &str, result: Result<Vec<String>, Box<dyn Error>>) -> String {
+    match result {
+        Ok(responses) => format!("{}: {}: {}", target.name, step, responses.join(", ")),
+        Err(e) => format!("{}: {}: failed ({})", target.name, step, e),
+    }
+}
+
+/// All at once, so that the lines of the TVs don't get mixed up
+fn print_report(event: &str, lines: &[String]) {
+    let report: Vec<String> = lines.iter().map(|line| format!("[{}] {}", event, line)).collect();
+    println!("{}", report.join("\n"));
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::error::ErrorClass;
+#[cfg(feature = "watch")]
+use crate::watch::REACHABLE;
+
+// the upper bounds of the buckets of the command latencies, in seconds
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Default)]
+struct Latencies {
+    count: u64,
+    sum: f64,
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+/// What the process has done so far, kept for the Prometheus exporter of lgster-server.
+/// Recording is cheap, so it's always done (even when nobody ever reads it).
+#[derive(Default)]
+struct Metrics {
+    latencies: Mutex<BTreeMap<String, Latencies>>,
+    failures: Mutex<BTreeMap<(String, ErrorClass), u64>>,
+    wake_packets: Mutex<BTreeMap<String, u64>>,
+    key_derivations: AtomicU64,
+    #[cfg(feature = "watch")]
+    tv_states: Mutex<BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Default::default)
+}
+
+/// A command sent to a TV, how long it took and how it went
+pub fn record_command(tv: &str, took: Duration, error: Option<&(dyn Error + 'static)>) {
+    let seconds = took.as_secs_f64();
+    let mut latencies = metrics().latencies.lock().unwrap();
+    let latencies = latencies.entry(tv.to_string()).or_default();
+    latencies.count += 1;
+    latencies.sum += seconds;
+    for (bucket, upper_bound) in latencies.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+        if seconds <= upper_bound {
+            *bucket += 1;
+        }
+    }
+
+    if let Some(error) = error {
+        *metrics()
+            .failures
+            .lock()
+            .unwrap()
+            .entry((tv.to_string(), ErrorClass::of(error)))
+            .or_default() += 1;
+    }
+}
+
+pub fn record_wake_packets(tv: &str, packets: u32) {
+    *metrics().wake_packets.lock().unwrap().entry(tv.to_string()).or_default() += packets as u64;
+}
+
+pub fn record_key_derivation() {
+    metrics().key_derivations.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The last known state of a TV (as polled by a `Watcher`), for its gauges
+#[cfg(feature = "watch")]
+pub fn record_tv_state(tv: &str, state: &BTreeMap<&'static str, String>) {
+    let state = state.iter().map(|(property, value)| (property.to_string(), value.clone())).collect();
+    metrics().tv_states.lock().unwrap().insert(tv.to_string(), state);
+}
+
+/// Everything recorded so far, in the Prometheus text format
+pub fn render() -> String {
+    let mut families: BTreeMap<String, (&str, &str, Vec<String>)> = BTreeMap::new();
+    let mut add = |name: &str, kind: &'static str, help: &'static str, sample: String| {
+        families
+            .entry(name.to_string())
+            .or_insert_with(|| (kind, help, vec![]))
+            .2
+            .push(sample);
+    };
+
+    #[cfg(feature = "watch")]
+    for (tv, state) in metrics().tv_states.lock().unwrap().iter() {
+        let tv_label = format!("tv=\"{}\"", escape_label(tv));
+        for (property, value) in state {
+            match (property.as_str(), value.as_str()) {
+                (REACHABLE, reachable) => add(
+                    "lgster_tv_reachable",
+                    "gauge",
+                    "Whether the TV answers (1) or not (0)",
+                    format!("lgster_tv_reachable{{{}}} {}", tv_label, (reachable == "true") as u8),
+                ),
+                ("mute", mute) => add(
+                    "lgster_tv_muted",
+                    "gauge",
+                    "Whether the TV is muted (1) or not (0)",
+                    format!("lgster_tv_muted{{{}}} {}", tv_label, (mute == "on") as u8),
+                ),
+                ("current-app", app) => add(
+                    "lgster_tv_current_app_info",
+                    "gauge",
+                    "The app (or input) the TV is showing",
+                    format!("lgster_tv_current_app_info{{{},app=\"{}\"}} 1", tv_label, escape_label(app)),
+                ),
+                // any other query with a number for an answer (e.g. volume) is a gauge of its own. There's
+                // no backlight gauge, since IP Control can only set it (PICTURE_BACKLIGHT), not query it.
+                (property, value) => {
+                    if let Ok(number) = value.parse::<f64>() {
+                        let name = format!("lgster_tv_{}", property.replace('-', "_"));
+                        let sample = format!("{}{{{}}} {}", name, tv_label, number);
+                        add(&name, "gauge", "The last polled value of this query", sample);
+                    }
+                }
+            }
+        }
+    }
+
+    for (tv, latencies) in metrics().latencies.lock().unwrap().iter() {
+        let tv_label = format!("tv=\"{}\"", escape_label(tv));
+        add(
+            "lgster_commands_total",
+            "counter",
+            "Commands sent to the TV",
+            format!("lgster_commands_total{{{}}} {}", tv_label, latencies.count),
+        );
+
+        let mut samples = vec![];
+        for (bucket, upper_bound) in latencies.buckets.iter().zip(LATENCY_BUCKETS) {
+            samples.push(format!(
+                "lgster_command_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                tv_label, upper_bound, bucket
+            ));
+        }
+        samples.push(format!(
+            "lgster_command_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            tv_label, latencies.count
+        ));
+        samples.push(format!("lgster_command_duration_seconds_sum{{{}}} {}", tv_label, latencies.sum));
+        samples.push(format!("lgster_command_duration_seconds_count{{{}}} {}", tv_label, latencies.count));
+        for sample in samples {
+            add(
+                "lgster_command_duration_seconds",
+                "histogram",
+                "How long the TV took to answer a command (or to fail)",
+                sample,
+            );
+        }
+    }
+
+    for ((tv, class), failures) in metrics().failures.lock().unwrap().iter() {
+        add(
+            "lgster_command_failures_total",
+            "counter",
+            "Commands that failed, by the class of the error",
+            format!(
+                "lgster_command_failures_total{{tv=\"{}\",class=\"{}\"}} {}",
+                escape_label(tv),
+                class.name(),
+                failures
+            ),
+        );
+    }
+
+    for (tv, packets) in metrics().wake_packets.lock().unwrap().iter() {
+        add(
+            "lgster_wake_packets_total",
+            "counter",
+            "Wake-on-lan packets sent to the TV",
+            format!("lgster_wake_packets_total{{tv=\"{}\"}} {}", escape_label(tv), packets),
+        );
+    }
+
+    add(
+        "lgster_key_derivations_total",
+        "counter",
+        "AES keys derived from keycodes (each one takes a noticeable while)",
+        format!("lgster_key_derivations_total {}", metrics().key_derivations.load(Ordering::Relaxed)),
+    );
+
+    let mut text = String::new();
+    for (name, (kind, help, samples)) in families {
+        // writing to a String can't fail
+        writeln!(text, "# HELP {} {}", name, help).unwrap();
+        writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+        for sample in samples {
+            writeln!(text, "{}", sample).unwrap();
+        }
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,413 @@
+use std::io::{self, BufReader, Error as IOError, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// only the bits of MQTT 3.1.1 we need: QoS 0 publishing and subscribing, retained messages and a will
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+// the commands we take are tiny, so a bigger packet is either a mistake or up to no good
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct MqttOptions {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The broker drops the connection if it doesn't hear from us for one and a half of these
+    pub keep_alive: Duration,
+    /// The topic and the payload the broker publishes (retained) when the connection is lost
+    pub will: Option<(String, String)>,
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+/// A bare-bones MQTT client. It can be shared between threads, so that one of them
+/// can wait for messages while others publish.
+pub struct MqttClient {
+    writer: Mutex<(TcpStream, Instant)>,
+    reader: Mutex<BufReader<TcpStream>>,
+    keep_alive: Duration,
+}
+
+impl MqttClient {
+    pub fn connect(options: &MqttOptions) -> io::Result<MqttClient> {
+        let stream = TcpStream::connect((options.host.as_str(), options.port))?;
+        // hearing nothing (not even the answers to our pings) for this long means the broker is gone
+        stream.set_read_timeout(Some(options.keep_alive * 2))?;
+        let client = MqttClient {
+            writer: Mutex::new((stream.try_clone()?, Instant::now())),
+            reader: Mutex::new(BufReader::new(stream)),
+            keep_alive: options.keep_alive,
+        };
+
+        client.send_packet(CONNECT, &connect_body(options))?;
+
+        let (packet_type, body) = client.read_packet()?;
+        match (packet_type & 0xf0, body.get(1)) {
+            (CONNACK, Some(0)) => Ok(client),
+            (CONNACK, Some(return_code)) => Err(IOError::new(
+                ErrorKind::ConnectionRefused,
+                format!("the MQTT broker refused the connection ({})", connack_reason(*return_code)),
+            )),
+            _ => Err(invalid_packet()),
+        }
+    }
+
+    pub fn subscribe(&self, topics: &[String]) -> io::Result<()> {
+        self.send_packet(SUBSCRIBE, &subscribe_body(topics))
+    }
+
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        self.send_packet(PUBLISH | retain as u8, &publish_body(topic, payload))
+    }
+
+    /// Pings the broker if nothing was sent for a while, so that it doesn't drop the connection.
+    /// It must be called every now and then by whoever is publishing.
+    pub fn keep_alive(&self) -> io::Result<()> {
+        let last_sent = self.writer.lock().unwrap().1;
+        if last_sent.elapsed() >= self.keep_alive / 2 {
+            self.send_packet(PINGREQ, &[])?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next message of the topics subscribed to, skipping everything else
+    pub fn next_message(&self) -> io::Result<Message> {
+        loop {
+            let (packet_type, body) = self.read_packet()?;
+            if packet_type & 0xf0 == PUBLISH {
+                return parse_publish(packet_type, &body);
+            }
+        }
+    }
+
+    /// Says goodbye to the broker (so that it doesn't publish the will) and closes the connection,
+    /// which also wakes up whoever is waiting for a message
+    pub fn disconnect(&self) {
+        let _ = self.send_packet(DISCONNECT, &[]);
+        let _ = self.writer.lock().unwrap().0.shutdown(Shutdown::Both);
+    }
+
+    fn send_packet(&self, packet_type: u8, body: &[u8]) -> io::Result<()> {
+        let packet = encode_packet(packet_type, body);
+        let mut writer = self.writer.lock().unwrap();
+        writer.0.write_all(&packet)?;
+        writer.1 = Instant::now();
+        Ok(())
+    }
+
+    fn read_packet(&self) -> io::Result<(u8, Vec<u8>)> {
+        let mut reader = self.reader.lock().unwrap();
+        let mut packet_type = [0u8; 1];
+        reader.read_exact(&mut packet_type)?;
+
+        // past the first byte, a timeout (or anything else) leaves us in the middle of a packet, with no
+        // way to tell where the next one starts, so the connection is closed and has to be made again
+        read_packet_rest(&mut *reader, packet_type[0]).map_err(|e| {
+            let _ = reader.get_ref().shutdown(Shutdown::Both);
+            IOError::new(ErrorKind::ConnectionAborted, format!("lost the MQTT packet being read ({})", e))
+        })
+    }
+}
+
+fn connect_body(options: &MqttOptions) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    let mut payload = encode_string(&options.client_id);
+    if let Some((topic, message)) = &options.will {
+        flags |= 0x04 | 0x20; // will, retained
+        payload.extend(encode_string(topic));
+        payload.extend(encode_string(message));
+    }
+    if let Some(username) = &options.username {
+        flags |= 0x80;
+        payload.extend(encode_string(username));
+    }
+    if let Some(password) = &options.password {
+        flags |= 0x40;
+        payload.extend(encode_string(password));
+    }
+
+    let mut body = encode_string("MQTT");
+    body.push(4); // protocol level of 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&(options.keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+    body.extend(payload);
+    body
+}
+
+fn subscribe_body(topics: &[String]) -> Vec<u8> {
+    // a single packet id is enough, since we never look at the SUBACKs
+    let mut body = 1u16.to_be_bytes().to_vec();
+    for topic in topics {
+        body.extend(encode_string(topic));
+        body.push(0); // QoS 0
+    }
+    body
+}
+
+fn publish_body(topic: &str, payload: &[u8]) -> Vec<u8> {
+    let mut body = encode_string(topic);
+    body.extend_from_slice(payload);
+    body
+}
+
+fn encode_packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    packet.extend(encode_remaining_length(body.len()));
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Reads the remaining length and the body of a packet whose first byte was already read
+fn read_packet_rest(reader: &mut impl Read, packet_type: u8) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    let mut remaining_length = 0usize;
+    for i in 0..4 {
+        reader.read_exact(&mut byte)?;
+        remaining_length |= ((byte[0] & 0x7f) as usize) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            if remaining_length > MAX_PACKET_SIZE {
+                return Err(IOError::new(
+                    ErrorKind::InvalidData,
+                    format!("the MQTT packet of {} bytes is too big", remaining_length),
+                ));
+            }
+            let mut body = vec![0u8; remaining_length];
+            reader.read_exact(&mut body)?;
+            return Ok((packet_type, body));
+        }
+    }
+    // the remaining length is at most 4 bytes long
+    Err(invalid_packet())
+}
+
+fn parse_publish(packet_type: u8, body: &[u8]) -> io::Result<Message> {
+    let topic_length = match body.get(..2) {
+        Some(length) => u16::from_be_bytes([length[0], length[1]]) as usize,
+        None => return Err(invalid_packet()),
+    };
+    let topic = body.get(2..2 + topic_length).ok_or_else(invalid_packet)?;
+    // QoS 1 and 2 messages have a packet id after the topic (even though we only ask for QoS 0)
+    let payload_start = match (packet_type >> 1) & 0x03 {
+        0 => 2 + topic_length,
+        _ => 4 + topic_length,
+    };
+
+    Ok(Message {
+        topic: String::from_utf8_lossy(topic).into_owned(),
+        payload: body.get(payload_start..).unwrap_or(&[]).to_vec(),
+    })
+}
+
+fn encode_string(string: &str) -> Vec<u8> {
+    let mut encoded = (string.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(string.as_bytes());
+    encoded
+}
+
+fn encode_remaining_length(mut length: usize) -> Vec<u8> {
+    let mut encoded = vec![];
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        encoded.push(byte);
+        if length == 0 {
+            return encoded;
+        }
+    }
+}
+
+fn connack_reason(return_code: u8) -> &'static str {
+    match return_code {
+        1 => "unacceptable protocol version",
+        2 => "client id rejected",
+        3 => "server unavailable",
+        4 => "bad username or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+fn invalid_packet() -> IOError {
+    IOError::new(ErrorKind::InvalidData, "invalid MQTT packet")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn options() -> MqttOptions {
+        MqttOptions {
+            host: "127.0.0.1".to_string(),
+            port: DEFAULT_MQTT_PORT,
+            client_id: "lgster".to_string(),
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(60),
+            will: None,
+        }
+    }
+
+    fn read_packet(bytes: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let mut reader = Cursor::new(bytes);
+        let mut packet_type = [0u8; 1];
+        reader.read_exact(&mut packet_type)?;
+        read_packet_rest(&mut reader, packet_type[0])
+    }
+
+    #[test]
+    fn encodes_remaining_lengths() {
+        assert_eq!(encode_remaining_length(0), [0x00]);
+        assert_eq!(encode_remaining_length(127), [0x7f]);
+        assert_eq!(encode_remaining_length(128), [0x80, 0x01]);
+        assert_eq!(encode_remaining_length(16_383), [0xff, 0x7f]);
+        assert_eq!(encode_remaining_length(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(encode_remaining_length(2_097_151), [0xff, 0xff, 0x7f]);
+        assert_eq!(encode_remaining_length(2_097_152), [0x80, 0x80, 0x80, 0x01]);
+        assert_eq!(encode_remaining_length(268_435_455), [0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn encodes_strings() {
+        assert_eq!(encode_string(""), [0, 0]);
+        assert_eq!(encode_string("lgster/tv"), b"\x00\x09lgster/tv");
+    }
+
+    #[test]
+    fn frames_connect() {
+        let packet = encode_packet(CONNECT, &connect_body(&options()));
+        assert_eq!(packet, b"\x10\x12\x00\x04MQTT\x04\x02\x00\x3c\x00\x06lgster");
+    }
+
+    #[test]
+    fn frames_connect_with_a_will_and_credentials() {
+        let options = MqttOptions {
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+            keep_alive: Duration::from_secs(100_000),
+            will: Some(("lgster/status".to_string(), "offline".to_string())),
+            ..options()
+        };
+        let mut expected = b"\x00\x04MQTT\x04\xe6\xff\xff\x00\x06lgster".to_vec();
+        expected.extend_from_slice(b"\x00\x0dlgster/status\x00\x07offline\x00\x04user\x00\x04pass");
+        assert_eq!(connect_body(&options), expected);
+    }
+
+    #[test]
+    fn frames_publish() {
+        let packet = encode_packet(PUBLISH | true as u8, &publish_body("tv/power", b"on"));
+        assert_eq!(packet, b"\x31\x0c\x00\x08tv/poweron");
+        let packet = encode_packet(PUBLISH, &publish_body("tv", &[b'x'; 200]));
+        assert_eq!(packet[..6], [0x30, 0xcc, 0x01, 0x00, 0x02, b't']);
+        assert_eq!(packet.len(), 3 + 204);
+    }
+
+    #[test]
+    fn frames_subscribe() {
+        let packet = encode_packet(SUBSCRIBE, &subscribe_body(&["a/+".to_string(), "b/#".to_string()]));
+        assert_eq!(packet, b"\x82\x0e\x00\x01\x00\x03a/+\x00\x00\x03b/#\x00");
+    }
+
+    #[test]
+    fn reads_packets() {
+        assert_eq!(read_packet(b"\x20\x02\x00\x00").unwrap(), (CONNACK, vec![0, 0]));
+        assert_eq!(read_packet(b"\xd0\x00").unwrap(), (0xd0, vec![]));
+
+        let mut long = vec![PUBLISH, 0x80, 0x01];
+        long.extend(vec![7; 128]);
+        assert_eq!(read_packet(&long).unwrap(), (PUBLISH, vec![7; 128]));
+
+        // what was written is what's read
+        let packet = encode_packet(PUBLISH, &publish_body("tv", &[1; 20_000]));
+        assert_eq!(read_packet(&packet).unwrap(), (PUBLISH, publish_body("tv", &[1; 20_000])));
+
+        let packet = encode_packet(PUBLISH, &vec![1; MAX_PACKET_SIZE]);
+        assert_eq!(read_packet(&packet).unwrap(), (PUBLISH, vec![1; MAX_PACKET_SIZE]));
+    }
+
+    #[test]
+    fn rejects_truncated_or_invalid_packets() {
+        assert_eq!(read_packet(b"\x30").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read_packet(b"\x30\x80").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read_packet(b"\x30\x05\x00\x01").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        // a remaining length of more than 4 bytes
+        let packet = b"\x30\xff\xff\xff\xff\x01";
+        assert_eq!(read_packet(packet).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_packets_that_are_too_big() {
+        // before reading (or allocating) the body
+        let mut packet = vec![PUBLISH];
+        packet.extend(encode_remaining_length(MAX_PACKET_SIZE + 1));
+        assert_eq!(read_packet(&packet).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_packet(b"\x30\xff\xff\xff\x7f").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parses_publish() {
+        let message = parse_publish(PUBLISH, b"\x00\x08tv/inputhdmi_1").unwrap();
+        assert_eq!(message.topic, "tv/input");
+        assert_eq!(message.payload, b"hdmi_1");
+
+        // retained, with an empty payload
+        let message = parse_publish(PUBLISH | 1, b"\x00\x02tv").unwrap();
+        assert_eq!(message.topic, "tv");
+        assert!(message.payload.is_empty());
+
+        // QoS 1, with a packet id
+        let message = parse_publish(PUBLISH | 0x02, b"\x00\x02tv\x00\x2aon").unwrap();
+        assert_eq!(message.topic, "tv");
+        assert_eq!(message.payload, b"on");
+    }
+
+    #[test]
+    fn rejects_invalid_publish() {
+        assert_eq!(parse_publish(PUBLISH, b"").unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(parse_publish(PUBLISH, b"\x00").unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(parse_publish(PUBLISH, b"\x00\x05tv").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn a_timeout_in_the_middle_of_a_packet_closes_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connect = [0u8; 20];
+            stream.read_exact(&mut connect).unwrap();
+            stream.write_all(b"\x20\x02\x00\x00").unwrap();
+            // half of a PUBLISH, and the rest too late
+            stream.write_all(b"\x30\x0c\x00\x08tv/").unwrap();
+            thread::sleep(Duration::from_millis(500));
+            let _ = stream.write_all(b"poweron");
+            let _ = stream.write_all(b"\x30\x0c\x00\x08tv/poweron");
+        });
+
+        let options = MqttOptions { port, keep_alive: Duration::from_millis(100), ..options() };
+        let client = MqttClient::connect(&options).unwrap();
+        assert_eq!(client.next_message().unwrap_err().kind(), ErrorKind::ConnectionAborted);
+        // rather than reading what's left of the packet as if it was the start of one
+        assert!(client.next_message().is_err());
+        broker.join().unwrap();
+    }
+}
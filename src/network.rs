@@ -1,22 +1,171 @@
+use log::debug;
 use std::io;
 use std::io::{Error as IOError, ErrorKind, Read, Write};
-use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
-use std::time::Duration;
-
-pub fn send_udp_message(target: &str, message: &[u8]) -> io::Result<usize> {
-    for port in 1025..=65535 {
-        let maybe_socket =
-            UdpSocket::bind(("0.0.0.0", port)).and_then(|s| s.set_broadcast(true).map(|_| s));
-        match maybe_socket {
-            Ok(s) => return s.send_to(message, (target, 9)),
-            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
-            Err(e) => return Err(e),
-        };
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::time::{Duration, Instant};
+
+/// Resolves the target of an UDP message, which may be a host or an IP address. IPv6 addresses
+/// may have a scope (e.g. `ff02::1%eth0`), which otherwise defaults to the `interface`.
+pub fn resolve_udp_target(target: &str, port: u16, interface: Option<&str>) -> io::Result<SocketAddr> {
+    let (host, scope) = match target.find('%') {
+        Some(i) => (&target[..i], Some(&target[i + 1..])),
+        None => (target, interface),
+    };
+
+    let address = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(_) => (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+            IOError::new(ErrorKind::InvalidInput, format!("couldn't resolve {}", host))
+        })?,
+    };
+
+    match address {
+        SocketAddr::V6(address) if is_link_local(address.ip()) => {
+            let scope = scope.ok_or_else(|| {
+                IOError::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is link-local, so it needs an interface (e.g. {}%eth0)", host, host),
+                )
+            })?;
+            let scope_id = match scope.parse::<u32>() {
+                Ok(scope_id) => scope_id,
+                Err(_) => interface_index(scope)?,
+            };
+            Ok(SocketAddrV6::new(*address.ip(), port, 0, scope_id).into())
+        }
+        address => Ok(address),
+    }
+}
+
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    // fe80::/10 unicast and ff02::/16 (link-local scope) multicast
+    first_segment & 0xffc0 == 0xfe80 || first_segment & 0xff0f == 0xff02
+}
+
+pub fn send_udp_message(
+    target: SocketAddr,
+    message: &[u8],
+    source_address: Option<IpAddr>,
+    interface: Option<&str>,
+) -> io::Result<usize> {
+    let source_address = match (source_address, interface, target) {
+        (Some(source_address), _, _) => source_address,
+        // binding to the address of the interface is what makes the kernel pick it for IPv4
+        // (the IPv6 targets that need an interface already have it as their scope)
+        (None, Some(interface), SocketAddr::V4(_)) => interface_ipv4_address(interface)?.into(),
+        (None, _, SocketAddr::V4(_)) => Ipv4Addr::UNSPECIFIED.into(),
+        (None, _, SocketAddr::V6(_)) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+
+    let socket = UdpSocket::bind((source_address, 0))?;
+    if target.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+    if let Some(interface) = interface {
+        bind_to_device(&socket, interface)?;
     }
 
+    socket.send_to(message, target)
+}
+
+#[cfg(unix)]
+fn interface_index(interface: &str) -> io::Result<u32> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|_| IOError::new(ErrorKind::InvalidInput, "invalid interface name"))?;
+
+    // safe because the name is a valid nul terminated string that outlives the call
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(IOError::new(
+            ErrorKind::NotFound,
+            format!("there's no interface {}", interface),
+        )),
+        index => Ok(index),
+    }
+}
+
+#[cfg(unix)]
+fn interface_ipv4_address(interface: &str) -> io::Result<Ipv4Addr> {
+    let mut interfaces: *mut libc::ifaddrs = std::ptr::null_mut();
+    // safe because getifaddrs only writes the head of the list it allocates to the pointer
+    if unsafe { libc::getifaddrs(&mut interfaces) } != 0 {
+        return Err(IOError::last_os_error());
+    }
+
+    let mut address = None;
+    let mut current = interfaces;
+    // safe because we only walk the list returned by getifaddrs before freeing it
+    unsafe {
+        while !current.is_null() && address.is_none() {
+            let entry = &*current;
+            let name = std::ffi::CStr::from_ptr(entry.ifa_name);
+            if name.to_bytes() == interface.as_bytes()
+                && !entry.ifa_addr.is_null()
+                && i32::from((*entry.ifa_addr).sa_family) == libc::AF_INET
+            {
+                let socket_address = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                address = Some(Ipv4Addr::from(u32::from_be(socket_address.sin_addr.s_addr)));
+            }
+            current = entry.ifa_next;
+        }
+        libc::freeifaddrs(interfaces);
+    }
+
+    address.ok_or_else(|| {
+        IOError::new(
+            ErrorKind::NotFound,
+            format!("there's no interface {} with an IPv4 address", interface),
+        )
+    })
+}
+
+/// Makes sure the packets leave through the interface, which binding
+/// to its address alone doesn't guarantee for broadcasts
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_device(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // safe because the buffer and its length are valid for the duration of the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => match IOError::last_os_error() {
+            // it requires CAP_NET_RAW, so when we don't have it we settle for the bound address
+            e if e.kind() == ErrorKind::PermissionDenied => Ok(()),
+            e => Err(e),
+        },
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_to_device(_socket: &UdpSocket, _interface: &str) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn interface_index(_interface: &str) -> io::Result<u32> {
+    Err(IOError::new(
+        ErrorKind::Unsupported,
+        "interfaces can't be given by name on this platform",
+    ))
+}
+
+#[cfg(not(unix))]
+fn interface_ipv4_address(_interface: &str) -> io::Result<Ipv4Addr> {
     Err(IOError::new(
-        ErrorKind::AddrNotAvailable,
-        "No address available (tried 0.0.0.0 from ports 1025 to 65535)",
+        ErrorKind::Unsupported,
+        "interfaces can't be given by name on this platform",
     ))
 }
 
@@ -29,15 +178,18 @@ pub fn send_and_receive_tcp_message(
         "message target address is empty",
     ))?;
 
+    let started_at = Instant::now();
     let mut tcp_stream =
         TcpStream::connect_timeout(&target_socket_address, Duration::from_secs(15))?;
+    debug!("connected to address={} took={:?}", target_socket_address, started_at.elapsed());
     tcp_stream.set_read_timeout(Some(Duration::from_secs(3)))?;
     tcp_stream.set_write_timeout(Some(Duration::from_secs(3)))?;
     tcp_stream.write_all(message)?;
     tcp_stream.flush()?;
 
     let mut response = vec![0; 128];
-    tcp_stream.read(&mut response[..16])?;
+    // the whole IV, even if it comes in pieces, and an error rather than a zeroed one if the TV hangs up first
+    tcp_stream.read_exact(&mut response[..16])?;
     let mut bytes_read = 16;
 
     // after it succesfully read the iv, it means the connection is ok
@@ -66,3 +218,72 @@ pub fn send_and_receive_tcp_message(
     response.resize(bytes_read, 0);
     Ok(response)
 }
+
+/// A bare-bones HTTP/1.0 client, just enough for talking to the TVs' UPnP descriptions
+/// and to local endpoints. Only plain `http://` URLs are supported.
+/// Returns the status code and the body of the response.
+pub fn send_http_request(
+    method: &str,
+    url: &str,
+    content: Option<(&str, &[u8])>,
+    timeout: Duration,
+) -> io::Result<(u16, Vec<u8>)> {
+    let invalid_url = || IOError::new(ErrorKind::InvalidInput, format!("invalid url {}", url));
+
+    let without_scheme = url.strip_prefix("http://").ok_or_else(invalid_url)?;
+    let (authority, path) = match without_scheme.find('/') {
+        Some(i) => without_scheme.split_at(i),
+        None => (without_scheme, "/"),
+    };
+    let target_socket_address = match authority.rfind(':') {
+        Some(i) if !authority.ends_with(']') => {
+            let port = authority[i + 1..].parse::<u16>().map_err(|_| invalid_url())?;
+            (&authority[..i], port).to_socket_addrs()?.next()
+        }
+        _ => (authority, 80).to_socket_addrs()?.next(),
+    }
+    .ok_or_else(invalid_url)?;
+
+    let mut tcp_stream = TcpStream::connect_timeout(&target_socket_address, timeout)?;
+    tcp_stream.set_read_timeout(Some(timeout))?;
+    tcp_stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n",
+        method, path, authority
+    )
+    .into_bytes();
+    if let Some((content_type, body)) = content {
+        request.extend_from_slice(
+            format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n",
+                content_type,
+                body.len()
+            )
+            .as_bytes(),
+        );
+        request.extend_from_slice(b"\r\n");
+        request.extend_from_slice(body);
+    } else {
+        request.extend_from_slice(b"\r\n");
+    }
+    tcp_stream.write_all(&request)?;
+    tcp_stream.flush()?;
+
+    // HTTP/1.0 with Connection: close means the body ends when the connection does
+    let mut response = vec![];
+    tcp_stream.read_to_end(&mut response)?;
+
+    let invalid_response = || IOError::new(ErrorKind::InvalidData, "invalid http response");
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(invalid_response)?;
+    let status = String::from_utf8_lossy(&response[..header_end])
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(invalid_response)?;
+
+    Ok((status, response.split_off(header_end + 4)))
+}
@@ -0,0 +1,301 @@
+use log::{debug, warn};
+use rand::Rng;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::comm::{aes_key_for, decrypt_command, decrypt_reply, encrypt_command, encrypt_reply};
+use crate::logging::Hex;
+use crate::target::Target;
+use crate::transport::{exchange_of, transport};
+
+// how long a client has to start sending its command once it's connected
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+// the IV and the longest command worth waiting for (the commands of the TVs are much shorter)
+const MAX_REQUEST_SIZE: usize = 16 + 96;
+
+#[derive(Debug)]
+pub struct InvalidProxyRuleError {
+    rule: String,
+}
+
+impl fmt::Display for InvalidProxyRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid rewrite {} (it should be like \"VOLUME_CONTROL *=VOLUME_CONTROL 20\")",
+            self.rule
+        )
+    }
+}
+
+impl Error for InvalidProxyRuleError {}
+
+/// What the proxy does with the commands that match `pattern`, which is either the whole
+/// command or, ending with `*`, a prefix of it (e.g. `KEY_ACTION *`)
+#[derive(Debug, Clone)]
+pub enum ProxyRule {
+    /// The TV gets `replacement` instead
+    Rewrite { pattern: String, replacement: String },
+    /// The TV doesn't get it at all, and the proxy answers with `reply` itself
+    /// (or, without one, closes the connection as if the TV didn't answer)
+    Block { pattern: String, reply: Option<String> },
+}
+
+impl ProxyRule {
+    /// From `PATTERN=REPLACEMENT`
+    pub fn rewrite(rule: &str) -> Result<ProxyRule, InvalidProxyRuleError> {
+        match rule.split_once('=') {
+            Some((pattern, replacement)) if !pattern.is_empty() && !replacement.is_empty() => {
+                Ok(ProxyRule::Rewrite {
+                    pattern: pattern.to_string(),
+                    replacement: replacement.to_string(),
+                })
+            }
+            _ => Err(InvalidProxyRuleError { rule: rule.to_string() }),
+        }
+    }
+
+    /// From `PATTERN` or `PATTERN=REPLY`
+    pub fn block(rule: &str) -> ProxyRule {
+        match rule.split_once('=') {
+            Some((pattern, reply)) => ProxyRule::Block {
+                pattern: pattern.to_string(),
+                reply: Some(reply.to_string()),
+            },
+            None => ProxyRule::Block {
+                pattern: rule.to_string(),
+                reply: None,
+            },
+        }
+    }
+
+    fn matches(&self, command: &str) -> bool {
+        let pattern = match self {
+            ProxyRule::Rewrite { pattern, .. } | ProxyRule::Block { pattern, .. } => pattern,
+        };
+        match pattern.strip_suffix('*') {
+            Some(prefix) => command.starts_with(prefix),
+            None => pattern == command,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+    pub listen: SocketAddr,
+    /// Only the first rule that matches a command is applied
+    pub rules: Vec<ProxyRule>,
+}
+
+/// A command that went through the proxy, as it's reported
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxiedCommand {
+    pub client: String,
+    /// The command as the client sent it, unless it couldn't be decrypted (e.g. it was encrypted
+    /// with another keycode), in which case it was passed on as it was
+    pub command: Option<String>,
+    /// What the TV got instead, when a rule rewrote the command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewritten: Option<String>,
+    pub blocked: bool,
+    pub reply: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub took_ms: f64,
+    /// Seconds since the Unix epoch
+    pub timestamp: f64,
+}
+
+/// Listens for IP Control clients (e.g. a control panel or another script), passing each
+/// of their commands on to the `upstream` TV and its reply back to them, while reporting
+/// every command and reply decrypted with the keycode of the TV. Each connection is served
+/// on its own thread, one command per connection (like the TVs do). It only returns when
+/// it can't listen anymore.
+pub fn run_proxy(
+    options: &ProxyOptions,
+    upstream: &Target,
+    report: impl Fn(&ProxiedCommand) + Sync,
+) -> Result<(), Box<dyn Error>> {
+    let aes_key = aes_key_for(upstream.keycode()?, &upstream.salt);
+    let listener = TcpListener::bind(options.listen)?;
+    debug!(
+        "proxying from address={} to host={} port={}",
+        listener.local_addr()?,
+        upstream.host,
+        upstream.port
+    );
+
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    // e.g. too many open files, which can go away by itself
+                    warn!("couldn't accept a client error={}", e);
+                    continue;
+                }
+            };
+            let (aes_key, report) = (&aes_key, &report);
+            scope.spawn(move || {
+                if let Some(proxied_command) = serve_client(stream, options, upstream, aes_key) {
+                    report(&proxied_command);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Proxies the command of a client, unless it goes away without sending one
+fn serve_client(
+    mut stream: TcpStream,
+    options: &ProxyOptions,
+    upstream: &Target,
+    aes_key: &[u8; 16],
+) -> Option<ProxiedCommand> {
+    let client = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+    let request = match read_request(&mut stream, aes_key) {
+        Ok(request) => request,
+        Err(e) => {
+            debug!("no command from client={} error={}", client, e);
+            return None;
+        }
+    };
+
+    let started_at = Instant::now();
+    let command = decrypt_command(&request, aes_key);
+    debug!("client={} sent request={} command={:?}", client, Hex(&request), command);
+    let rule = command
+        .as_deref()
+        .and_then(|command| options.rules.iter().find(|rule| rule.matches(command)));
+
+    let mut proxied_command = ProxiedCommand {
+        client,
+        command: command.clone(),
+        rewritten: None,
+        blocked: false,
+        reply: None,
+        error: None,
+        took_ms: 0.0,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs_f64())
+            .unwrap_or_default(),
+    };
+    let response = match rule {
+        Some(ProxyRule::Block { reply, .. }) => {
+            proxied_command.blocked = true;
+            proxied_command.reply = reply.clone();
+            reply.as_deref().map(|reply| {
+                let iv: [u8; 16] = rand::thread_rng().gen();
+                Ok(encrypt_reply(reply, &iv, aes_key))
+            })
+        }
+        Some(ProxyRule::Rewrite { replacement, .. }) => {
+            proxied_command.rewritten = Some(replacement.clone());
+            let iv: [u8; 16] = rand::thread_rng().gen();
+            Some(exchange_with_tv(upstream, replacement, &encrypt_command(replacement, &iv, aes_key), aes_key))
+        }
+        // passed on as it was, so that the TV gets exactly what the client sent
+        None => Some(exchange_with_tv(upstream, command.as_deref().unwrap_or(""), &request, aes_key)),
+    };
+
+    match response {
+        Some(Ok(response)) => {
+            if !proxied_command.blocked {
+                proxied_command.reply = decrypt_reply(&response, aes_key).ok().map(|reply| reply.trim_end().to_string());
+            }
+            if let Err(e) = stream.write_all(&response).and_then(|_| stream.flush()) {
+                proxied_command.error = Some(format!("couldn't pass the reply on to the client ({})", e));
+            }
+        }
+        Some(Err(e)) => proxied_command.error = Some(e.to_string()),
+        None => {}
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+
+    proxied_command.took_ms = (started_at.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0;
+    Some(proxied_command)
+}
+
+/// Reads what the client sent which, like the responses of the TVs, doesn't say how long it is. So
+/// it ends either when it decrypts to a whole command or when nothing else comes for a moment
+/// after a whole block.
+fn read_request(stream: &mut TcpStream, aes_key: &[u8; 16]) -> io::Result<Vec<u8>> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(Duration::from_secs(3)))?;
+    let mut request = vec![0; 16];
+    stream.read_exact(&mut request)?;
+
+    let deadline = Instant::now() + CLIENT_TIMEOUT;
+    stream.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let mut buffer = [0; MAX_REQUEST_SIZE];
+    while request.len() < MAX_REQUEST_SIZE && decrypt_command(&request, aes_key).is_none() {
+        match stream.read(&mut buffer[..MAX_REQUEST_SIZE - request.len()]) {
+            Ok(0) => break,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+            // see `network::send_and_receive_tcp_message` for why both kinds
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                // a block split across segments is still coming
+                if request.len() % 16 == 0 {
+                    break;
+                } else if Instant::now() >= deadline {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "the request stopped in the middle of a block"));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(request)
+}
+
+fn exchange_with_tv(upstream: &Target, command: &str, request: &[u8], aes_key: &[u8; 16]) -> io::Result<Vec<u8>> {
+    let transport = transport();
+    let started_at = Instant::now();
+    let result = transport.exchange(&upstream.host, upstream.port, command, request);
+    let took = started_at.elapsed();
+
+    let exchange = match &result {
+        Ok(response) => {
+            let reply = decrypt_reply(response, aes_key).unwrap_or_default();
+            exchange_of(&upstream.host, upstream.port, command, request, Ok((response, &reply)), took)
+        }
+        Err(e) => exchange_of(&upstream.host, upstream.port, command, request, Err(e), took),
+    };
+    transport.exchanged(&exchange);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AES_KEY: [u8; 16] = *b"0123456789abcdef";
+
+    #[test]
+    fn keeps_the_blocks_split_across_segments() {
+        let request = encrypt_command("VOLUME_CONTROL 12", &[7; 16], &AES_KEY);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = {
+            let request = request.clone();
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                // the rest of the block comes after the moment the end of the request is waited for
+                stream.write_all(&request[..24]).unwrap();
+                thread::sleep(Duration::from_millis(300));
+                stream.write_all(&request[24..]).unwrap();
+                thread::sleep(Duration::from_millis(300));
+            })
+        };
+
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(read_request(&mut stream, &AES_KEY).unwrap(), request);
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! The Python extension module (built with maturin, see pyproject.toml and lgster.pyi), whose
+//! `Client` wraps a `Target` and raises an exception of its own for each `ErrorClass`
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::actions::run_action;
+use crate::apps::AppCatalog;
+use crate::commands::{generate_command_definitions, Command};
+use crate::comm::{send_wol_packet, WakeOptions};
+use crate::config::{Config, TvProfile, WakeSettings};
+use crate::error::ErrorClass;
+use crate::target::{Target, DEFAULT_PORT};
+use crate::wake::{mac_address_from_reply, wake_up, wake_up_and_wait, DEFAULT_WAKE_TARGET};
+
+create_exception!(lgster, LgsterError, PyException, "Any error of lgster");
+create_exception!(lgster, InvalidRequestError, LgsterError, "The command or its parameters are wrong");
+create_exception!(lgster, NotFoundError, LgsterError, "There's no such command, action, TV profile or group");
+create_exception!(lgster, UnauthorizedError, LgsterError, "The request didn't bring the right credentials");
+create_exception!(
+    lgster,
+    NotConfiguredError,
+    LgsterError,
+    "The TV lacks something the command needs (e.g. a keycode or a MAC address)"
+);
+create_exception!(lgster, UnreachableError, LgsterError, "The TV couldn't be reached");
+create_exception!(lgster, TimeoutError, LgsterError, "The TV didn't answer in time");
+create_exception!(lgster, InternalError, LgsterError, "Anything else");
+
+/// An error that can cross back from the threads that run without the GIL
+struct ClassifiedError {
+    class: ErrorClass,
+    message: String,
+}
+
+impl From<Box<dyn Error>> for ClassifiedError {
+    fn from(error: Box<dyn Error>) -> Self {
+        ClassifiedError {
+            class: ErrorClass::of(error.as_ref()),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<ClassifiedError> for PyErr {
+    fn from(error: ClassifiedError) -> Self {
+        let message = error.message;
+        match error.class {
+            ErrorClass::InvalidRequest => InvalidRequestError::new_err(message),
+            ErrorClass::NotFound => NotFoundError::new_err(message),
+            ErrorClass::Unauthorized => UnauthorizedError::new_err(message),
+            ErrorClass::NotConfigured => NotConfiguredError::new_err(message),
+            ErrorClass::Unreachable => UnreachableError::new_err(message),
+            ErrorClass::Timeout => TimeoutError::new_err(message),
+            ErrorClass::Internal => InternalError::new_err(message),
+        }
+    }
+}
+
+/// A TV to send commands to
+#[pyclass(module = "lgster", frozen)]
+struct Client {
+    target: Target,
+    config: Config,
+    commands: Vec<Command>,
+}
+
+impl Client {
+    fn new_with(target_name: &str, config: Config) -> PyResult<Client> {
+        let target = Target::resolve(target_name, &config).map_err(ClassifiedError::from)?;
+        Ok(Client {
+            target,
+            config,
+            commands: generate_command_definitions(),
+        })
+    }
+
+    /// Runs an action of the command catalog (like the lgster CLI does), without holding the GIL
+    fn run_action(&self, py: Python, command_name: &str, action: &str, parameters: &[String]) -> PyResult<Vec<String>> {
+        let command = self
+            .commands
+            .iter()
+            .find(|command| command.command_name == command_name)
+            .ok_or_else(|| NotFoundError::new_err(format!("There's no command {}", command_name)))?;
+
+        py.allow_threads(|| {
+            let app_catalog = AppCatalog::with_user_apps(&self.config.apps);
+            run_action(command, action, parameters, &self.target, &self.config, &app_catalog)
+                .map_err(ClassifiedError::from)
+        })
+        .map_err(PyErr::from)
+    }
+
+    fn run_single(&self, py: Python, command_name: &str, action: &str, parameters: &[String]) -> PyResult<String> {
+        Ok(self.run_action(py, command_name, action, parameters)?.into_iter().next().unwrap_or_default())
+    }
+
+    /// For the commands that change something, whose reply is OK unless the TV refused them
+    fn run_ok(&self, py: Python, command_name: &str, action: &str, parameters: &[String]) -> PyResult<()> {
+        match self.run_single(py, command_name, action, parameters)? {
+            reply if reply == "OK" => Ok(()),
+            reply => Err(InvalidRequestError::new_err(format!(
+                "The TV answered {} to {} {}",
+                reply, command_name, action
+            ))),
+        }
+    }
+}
+
+/// The value of a reply like `VOL:5`
+fn reply_value<'a>(reply: &'a str, prefix: &str) -> PyResult<&'a str> {
+    reply
+        .strip_prefix(prefix)
+        .map(str::trim)
+        .ok_or_else(|| InternalError::new_err(format!("Unexpected reply {} (it should start with {})", reply, prefix)))
+}
+
+#[pymethods]
+impl Client {
+    /// A TV given by its host (the MAC address is only needed to wake it up)
+    #[new]
+    #[pyo3(signature = (host, keycode=None, *, port=DEFAULT_PORT, salt=None, mac=None, wake_target=None))]
+    fn new(
+        host: &str,
+        keycode: Option<String>,
+        port: u16,
+        salt: Option<String>,
+        mac: Option<String>,
+        wake_target: Option<String>,
+    ) -> PyResult<Client> {
+        // made into a TV profile, which is what the wake-up and the favourite channels go by
+        let mut config = Config::default();
+        config.tvs.insert(
+            host.to_string(),
+            TvProfile {
+                host: host.to_string(),
+                port: Some(port),
+                keycode,
+                salt,
+                mac,
+                wake: wake_target.map(|target| WakeSettings {
+                    target: Some(target),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        Client::new_with(host, config)
+    }
+
+    /// A TV profile of the config file (~/.config/lgster/config.toml by default)
+    #[staticmethod]
+    #[pyo3(signature = (name, config=None))]
+    fn from_profile(name: &str, config: Option<PathBuf>) -> PyResult<Client> {
+        let config = match config.or_else(Config::default_path) {
+            Some(path) => Config::load(&path).map_err(|e| ClassifiedError::from(Box::new(e) as Box<dyn Error>))?,
+            None => Config::default(),
+        };
+        if !config.tvs.contains_key(name) {
+            return Err(NotFoundError::new_err(format!("There's no TV profile {}", name)));
+        }
+        Client::new_with(name, config)
+    }
+
+    #[getter]
+    fn host(&self) -> &str {
+        &self.target.host
+    }
+
+    #[getter]
+    fn port(&self) -> u16 {
+        self.target.port
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Client({:?}, port={})", self.target.name, self.target.port)
+    }
+
+    /// Sends a raw IP Control command (e.g. `VOLUME_CONTROL 10`), returning the reply
+    fn send(&self, py: Python, command: String) -> PyResult<String> {
+        py.allow_threads(|| self.target.send(command).map_err(ClassifiedError::from))
+            .map(|reply| reply.trim_end().to_string())
+            .map_err(PyErr::from)
+    }
+
+    /// Runs any command of the lgster CLI (e.g. `run("input", "hdmi-1")`), returning its replies
+    #[pyo3(signature = (command, action, *parameters))]
+    fn run(&self, py: Python, command: &str, action: &str, parameters: Vec<String>) -> PyResult<Vec<String>> {
+        self.run_action(py, command, action, &parameters)
+    }
+
+    fn query_volume(&self, py: Python) -> PyResult<u32> {
+        let reply = self.run_single(py, "query", "volume", &[])?;
+        reply_value(&reply, "VOL:")?
+            .parse()
+            .map_err(|_| InternalError::new_err(format!("Unexpected reply {}", reply)))
+    }
+
+    fn query_mute(&self, py: Python) -> PyResult<bool> {
+        let reply = self.run_single(py, "query", "mute", &[])?;
+        Ok(reply_value(&reply, "MUTE:")? == "on")
+    }
+
+    /// The friendly name of the app (e.g. `netflix`) or, when there's none, its id
+    fn query_current_app(&self, py: Python) -> PyResult<String> {
+        self.run_single(py, "query", "current-app", &[])
+    }
+
+    /// The MAC addresses of the wired and wifi interfaces, as `{"wired": ..., "wifi": ...}`
+    fn query_mac_addresses(&self, py: Python) -> PyResult<BTreeMap<String, Option<String>>> {
+        let replies = self.run_action(py, "query", "mac-addresses", &[])?;
+        Ok(["wired", "wifi"]
+            .iter()
+            .zip(replies)
+            .map(|(interface, reply)| (interface.to_string(), mac_address_from_reply(&reply)))
+            .collect())
+    }
+
+    fn set_volume(&self, py: Python, volume: u32) -> PyResult<()> {
+        self.run_ok(py, "set", "volume", &[volume.to_string()])
+    }
+
+    fn set_mute(&self, py: Python, muted: bool) -> PyResult<()> {
+        let muted = if muted { "on" } else { "off" };
+        self.run_ok(py, "set", "mute", &[muted.to_string()])
+    }
+
+    fn set_backlight(&self, py: Python, backlight: u32) -> PyResult<()> {
+        self.run_ok(py, "set", "backlight", &[backlight.to_string()])
+    }
+
+    /// An input (e.g. `hdmi-1`) of `lgster input`
+    fn set_input(&self, py: Python, input: &str) -> PyResult<()> {
+        self.run_ok(py, "input", input, &[])
+    }
+
+    /// An app by its friendly name (e.g. `netflix`) or its id
+    fn launch_app(&self, py: Python, app: String) -> PyResult<()> {
+        self.run_ok(py, "app", "launch", &[app])
+    }
+
+    /// A channel (e.g. `7-1`) or, for TV profiles, one of its favourites
+    fn set_channel(&self, py: Python, channel: String) -> PyResult<()> {
+        self.run_ok(py, "channel", "set", &[channel])
+    }
+
+    /// A key of the remote (e.g. `ok` or `vol-up`) of `lgster key`
+    fn press_key(&self, py: Python, key: &str) -> PyResult<()> {
+        self.run_ok(py, "key", key, &[])
+    }
+
+    fn power_off(&self, py: Python) -> PyResult<()> {
+        self.run_ok(py, "power", "off", &[])
+    }
+
+    /// Sends the wake-on-lan packet and, with `wait` (in seconds), waits until the TV answers,
+    /// returning how long that took
+    #[pyo3(signature = (wait=None))]
+    fn wake(&self, py: Python, wait: Option<f64>) -> PyResult<Option<f64>> {
+        py.allow_threads(|| match wait {
+            Some(wait) => Duration::try_from_secs_f64(wait)
+                .map_err(|e| Box::new(e) as Box<dyn Error>)
+                .and_then(|wait| wake_up_and_wait(&self.target, &self.config, wait))
+                .map(|took| Some(took.as_secs_f64())),
+            None => wake_up(&self.target, &self.config).map(|_| None),
+        }
+        .map_err(ClassifiedError::from))
+        .map_err(PyErr::from)
+    }
+}
+
+/// Sends the wake-on-lan packet for a MAC address without a `Client`
+#[pyfunction]
+#[pyo3(signature = (mac, target=None))]
+fn wake(py: Python, mac: String, target: Option<String>) -> PyResult<()> {
+    py.allow_threads(|| {
+        send_wol_packet(target.as_deref().unwrap_or(DEFAULT_WAKE_TARGET), &mac, &WakeOptions::default())
+            .map(|_| ())
+            .map_err(ClassifiedError::from)
+    })
+    .map_err(PyErr::from)
+}
+
+#[pymodule]
+fn lgster(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add_class::<Client>()?;
+    m.add_function(wrap_pyfunction!(wake, m)?)?;
+    m.add("LgsterError", py.get_type::<LgsterError>())?;
+    m.add("InvalidRequestError", py.get_type::<InvalidRequestError>())?;
+    m.add("NotFoundError", py.get_type::<NotFoundError>())?;
+    m.add("UnauthorizedError", py.get_type::<UnauthorizedError>())?;
+    m.add("NotConfiguredError", py.get_type::<NotConfiguredError>())?;
+    m.add("UnreachableError", py.get_type::<UnreachableError>())?;
+    m.add("TimeoutError", py.get_type::<TimeoutError>())?;
+    m.add("InternalError", py.get_type::<InternalError>())?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use http::{header, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+use crate::actions::{run_action, UsageError};
+use crate::apps::AppCatalog;
+use crate::commands::{generate_command_definitions, Command};
+use crate::config::Config;
+use crate::error::ErrorClass;
+use crate::target::Target;
+
+#[derive(Serialize)]
+struct TvSummary<'a> {
+    name: &'a str,
+    host: &'a str,
+}
+
+#[derive(Serialize)]
+struct CommandSummary<'a> {
+    command: &'a str,
+    description: &'a str,
+    method: String,
+    actions: Vec<ActionSummary<'a>>,
+}
+
+#[derive(Serialize)]
+struct ActionSummary<'a> {
+    name: &'a str,
+    /// Whether it needs a `value` (e.g. `set volume`)
+    takes_value: bool,
+}
+
+#[derive(Serialize)]
+struct ActionResult<'a> {
+    tv: &'a str,
+    responses: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    class: ErrorClass,
+}
+
+/// The JSON body of a POST, which is entirely optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ActionBody {
+    /// The parameter of the action (e.g. the volume of `set volume`)
+    value: Option<Value>,
+    /// The parameters of the actions that take more than one
+    values: Vec<Value>,
+    /// How many seconds `wake` waits for the TV to answer, if at all
+    wait: Option<f64>,
+}
+
+/// The web remote, a single page that works with the API below and is generated by itself from
+/// the command catalog (so it has to be served from the same place as the API)
+const REMOTE_PAGE: &str = include_str!("../assets/remote.html");
+
+/// Answers a request to the REST API. `path` is the part of the path after wherever the API
+/// is mounted (e.g. the PATH_INFO of a CGI script), and only the TV profiles of `config` can
+/// be controlled through it.
+///
+/// The routes are:
+/// - `GET /`: the web remote
+/// - `GET /tv`: the TV profiles
+/// - `GET /commands`: the command catalog
+/// - `GET /tv/<profile>/query/<action>`: the `query` commands
+/// - `POST /tv/<profile>/<command>/<action>`: every other command, e.g. `set/volume` with `{"value":10}`
+/// - `POST /tv/<profile>/wake`: the same as `power on`, waiting for the TV with `{"wait":60}`
+pub fn handle_request(request: &Request<Vec<u8>>, path: &str, config: &Config) -> Response<Vec<u8>> {
+    let commands = generate_command_definitions();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method();
+
+    match segments.as_slice() {
+        [] if method == Method::GET => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CONTENT_LENGTH, REMOTE_PAGE.len().to_string().as_str())
+            .body(REMOTE_PAGE.as_bytes().to_vec())
+            .unwrap(),
+        [] => method_not_allowed(Method::GET),
+        ["tv"] if method == Method::GET => json_response(StatusCode::OK, &list_tvs(config)),
+        ["commands"] if method == Method::GET => {
+            json_response(StatusCode::OK, &list_commands(&commands))
+        }
+        ["tv"] | ["commands"] => method_not_allowed(Method::GET),
+        ["tv", profile, "wake"] if method == Method::POST => {
+            let body = match parse_body(request) {
+                Ok(body) => body,
+                Err(e) => return error_response(e.as_ref()),
+            };
+            let parameters = match body.wait {
+                Some(seconds) if !seconds.is_finite() || seconds < 0.0 => {
+                    return error_response(&UsageError::new("{\"wait\": <seconds, at least 0>}"));
+                }
+                Some(seconds) => vec!["--wait".to_string(), seconds.to_string()],
+                None => vec![],
+            };
+            // the catalog always has `power on`
+            let power = commands.iter().find(|c| c.command_name == "power").unwrap();
+            run(profile, power, "on", &parameters, config)
+        }
+        ["tv", _, "wake"] => method_not_allowed(Method::POST),
+        ["tv", profile, command_name, action] => {
+            let command = match commands.iter().find(|c| c.command_name == *command_name) {
+                Some(command) => command,
+                None => return not_found(&format!("There's no command {}", command_name)),
+            };
+            // favourite channels are only changed from the command line, since the config
+            // file usually can't be written by the web server
+            if command.command_name == "channel" && *action != "set" {
+                return not_found(&format!("channel {} is only available from the command line", action));
+            }
+
+            let expected_method = method_of(command);
+            if *method != expected_method {
+                return method_not_allowed(expected_method);
+            }
+            let parameters: Vec<String> = match parse_body(request) {
+                Ok(body) => body.value.into_iter().chain(body.values).map(parameter_from_json).collect(),
+                Err(e) => return error_response(e.as_ref()),
+            };
+            run(profile, command, action, &parameters, config)
+        }
+        _ => not_found(&format!("There's nothing at {}", path)),
+    }
+}
+
+/// Whether a request can be answered without credentials, which is only the case for the web
+/// remote itself (it asks for the token on its own)
+pub fn is_public(request: &Request<Vec<u8>>, path: &str) -> bool {
+    request.method() == Method::GET && path.split('/').all(str::is_empty)
+}
+
+/// A JSON error response, with the status code of the class of the error
+pub fn error_response(error: &(dyn Error + 'static)) -> Response<Vec<u8>> {
+    let class = ErrorClass::of(error);
+    let status = match class {
+        ErrorClass::InvalidRequest => StatusCode::BAD_REQUEST,
+        ErrorClass::NotFound => StatusCode::NOT_FOUND,
+        ErrorClass::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorClass::NotConfigured => StatusCode::CONFLICT,
+        ErrorClass::Unreachable => StatusCode::BAD_GATEWAY,
+        ErrorClass::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorClass::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    json_response(
+        status,
+        &ErrorBody {
+            error: &error.to_string(),
+            class,
+        },
+    )
+}
+
+/// The response to a request without the right bearer token
+pub fn unauthorized_response() -> Response<Vec<u8>> {
+    let mut response = json_response(
+        StatusCode::UNAUTHORIZED,
+        &ErrorBody {
+            error: "Missing or wrong bearer token",
+            class: ErrorClass::Unauthorized,
+        },
+    );
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+    response
+}
+
+fn run(
+    profile: &str,
+    command: &Command,
+    action: &str,
+    parameters: &[String],
+    config: &Config,
+) -> Response<Vec<u8>> {
+    // only TV profiles, since anything else would make it possible to send commands to any host
+    if !config.tvs.contains_key(profile) {
+        return not_found(&format!("There's no TV profile {} in the config file", profile));
+    }
+
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+    let result = Target::resolve(profile, config)
+        .and_then(|target| run_action(command, action, parameters, &target, config, &app_catalog));
+
+    match result {
+        Ok(responses) => json_response(
+            StatusCode::OK,
+            &ActionResult {
+                tv: profile,
+                responses,
+            },
+        ),
+        Err(e) => error_response(e.as_ref()),
+    }
+}
+
+fn list_tvs(config: &Config) -> Vec<TvSummary<'_>> {
+    config
+        .tvs
+        .iter()
+        .map(|(name, tv_profile)| TvSummary {
+            name,
+            host: &tv_profile.host,
+        })
+        .collect()
+}
+
+fn list_commands(commands: &[Command]) -> Vec<CommandSummary<'_>> {
+    commands
+        .iter()
+        .map(|command| CommandSummary {
+            command: command.command_name,
+            description: command.command_info,
+            method: method_of(command).to_string(),
+            actions: command
+                .command_actions
+                .iter()
+                .filter(|(action, _)| command.command_name != "channel" || *action == "set")
+                .map(|(action, templates)| ActionSummary {
+                    name: action,
+                    takes_value: templates.iter().any(|template| template.contains("{}")),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Queries don't change anything, so they're the only commands that can be sent with a GET
+fn method_of(command: &Command) -> Method {
+    match command.command_name {
+        "query" => Method::GET,
+        _ => Method::POST,
+    }
+}
+
+fn parse_body(request: &Request<Vec<u8>>) -> Result<ActionBody, Box<dyn Error>> {
+    if request.body().iter().all(u8::is_ascii_whitespace) {
+        return Ok(ActionBody::default());
+    }
+    Ok(serde_json::from_slice(request.body())?)
+}
+
+fn parameter_from_json(value: Value) -> String {
+    match value {
+        Value::String(value) => value,
+        value => value.to_string(),
+    }
+}
+
+fn not_found(message: &str) -> Response<Vec<u8>> {
+    json_response(
+        StatusCode::NOT_FOUND,
+        &ErrorBody {
+            error: message,
+            class: ErrorClass::NotFound,
+        },
+    )
+}
+
+fn method_not_allowed(allowed: Method) -> Response<Vec<u8>> {
+    let mut response = json_response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        &ErrorBody {
+            error: &format!("Only {} is allowed here", allowed),
+            class: ErrorClass::InvalidRequest,
+        },
+    );
+    response
+        .headers_mut()
+        .insert(header::ALLOW, header::HeaderValue::from_str(allowed.as_str()).unwrap());
+    response
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Vec<u8>> {
+    // serializing these plain structs can't fail
+    let body = serde_json::to_vec(body).unwrap();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .header(header::CONTENT_LENGTH, body.len().to_string().as_str())
+        .body(body)
+        .unwrap()
+}
@@ -0,0 +1,534 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, TimeDelta, TimeZone, Weekday};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use crate::actions::{check_action, run_action, UnknownActionError};
+use crate::apps::AppCatalog;
+use crate::commands::Command;
+use crate::config::{Config, MissedRuns};
+use crate::fanout::run_concurrently;
+use crate::target::Target;
+
+const DEFAULT_GRACE: Duration = Duration::from_secs(60 * 60);
+// how late a run can be and still count as on time (e.g. the previous run of the loop took a while)
+const ON_TIME: Duration = Duration::from_secs(60);
+// the clock is checked at least this often, so that jumps (e.g. waking up from a suspend) are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub struct InvalidRuleError {
+    rule: String,
+    reason: String,
+}
+
+impl fmt::Display for InvalidRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid schedule `{}` ({})", self.rule, self.reason)
+    }
+}
+
+impl Error for InvalidRuleError {}
+
+#[derive(Debug)]
+pub struct InvalidDurationError {
+    duration: String,
+}
+
+impl fmt::Display for InvalidDurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid duration {} (it should be like 90s, 45m or 1h30m)", self.duration)
+    }
+}
+
+impl Error for InvalidDurationError {}
+
+/// Parses a duration like `90s`, `45m`, `2h` or `1h30m`
+pub fn parse_duration(duration: &str) -> Result<Duration, InvalidDurationError> {
+    let invalid = || InvalidDurationError {
+        duration: duration.to_string(),
+    };
+
+    let mut seconds = 0u64;
+    let mut number = String::new();
+    for c in duration.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let unit_seconds = number.parse::<u64>().map_err(|_| invalid())?.checked_mul(unit).ok_or_else(invalid)?;
+        seconds = seconds.checked_add(unit_seconds).ok_or_else(invalid)?;
+        number.clear();
+    }
+
+    match (number.is_empty(), seconds) {
+        (true, seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(invalid()),
+    }
+}
+
+/// When a schedule runs: at some times of the day (local time), on some days of the week.
+/// It's written like `weekdays 08:00`, `mon,wed,fri 12:00,18:30`, `sat-sun 10:00` or just `22:30`
+/// (every day), where the days can also be `daily` or `weekends`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Indexed by the number of days from Monday
+    days: [bool; 7],
+    /// Sorted
+    times: Vec<NaiveTime>,
+}
+
+impl FromStr for Rule {
+    type Err = InvalidRuleError;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| InvalidRuleError {
+            rule: rule.to_string(),
+            reason,
+        };
+
+        let mut days = None;
+        let mut times = vec![];
+        for part in rule.split(|c: char| c.is_whitespace() || c == ',').filter(|part| !part.is_empty()) {
+            if part.contains(':') {
+                let time = NaiveTime::parse_from_str(part, "%H:%M")
+                    .map_err(|_| invalid(format!("{} isn't a time like 08:00", part)))?;
+                times.push(time);
+                continue;
+            }
+
+            let days = days.get_or_insert([false; 7]);
+            for day in days_of(part).ok_or_else(|| invalid(format!("{} isn't a day like mon or weekdays", part)))? {
+                days[day.num_days_from_monday() as usize] = true;
+            }
+        }
+
+        if times.is_empty() {
+            return Err(invalid("it needs at least one time, like 08:00".to_string()));
+        }
+        times.sort();
+        times.dedup();
+        Ok(Rule {
+            days: days.unwrap_or([true; 7]),
+            times,
+        })
+    }
+}
+
+/// The days of a part of a rule, which is either a name for many days, a day or a range of days
+fn days_of(part: &str) -> Option<Vec<Weekday>> {
+    let range = |first: Weekday, last: Weekday| {
+        let mut days = vec![first];
+        while *days.last().unwrap() != last {
+            days.push(days.last().unwrap().succ());
+        }
+        days
+    };
+
+    match part.to_lowercase().as_str() {
+        "daily" | "everyday" => Some(range(Weekday::Mon, Weekday::Sun)),
+        "weekdays" => Some(range(Weekday::Mon, Weekday::Fri)),
+        "weekends" => Some(range(Weekday::Sat, Weekday::Sun)),
+        part => match part.split_once('-') {
+            Some((first, last)) => Some(range(first.parse().ok()?, last.parse().ok()?)),
+            None => Some(vec![part.parse().ok()?]),
+        },
+    }
+}
+
+impl Rule {
+    /// The last time it should have run after `after` and until `until`, if any
+    pub fn latest_between<Tz: TimeZone>(&self, after: DateTime<Tz>, until: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let mut date = until.date_naive();
+        while date >= after.date_naive() {
+            for time in self.times_on(until.timezone(), date).rev() {
+                if time <= until {
+                    return Some(time).filter(|time| *time > after);
+                }
+            }
+            date = date.pred_opt()?;
+        }
+        None
+    }
+
+    /// The next time it runs after `after`
+    pub fn next_after<Tz: TimeZone>(&self, after: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let mut date = after.date_naive();
+        // a week always has at least one of the days, but the times of a day may not exist (DST)
+        for _ in 0..8 {
+            if let Some(time) = self.times_on(after.timezone(), date).find(|time| *time > after) {
+                return Some(time);
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    /// The times it runs on `date`, in the time zone of the schedule (`Local`, but for the tests)
+    fn times_on<'a, Tz: TimeZone + 'a>(
+        &'a self,
+        zone: Tz,
+        date: NaiveDate,
+    ) -> impl DoubleEndedIterator<Item = DateTime<Tz>> + 'a {
+        let runs_that_day = self.days[date.weekday().num_days_from_monday() as usize];
+        self.times
+            .iter()
+            .filter(move |_| runs_that_day)
+            // a time that is skipped by DST doesn't happen that day, and one that happens twice only runs once
+            .filter_map(move |time| zone.from_local_datetime(&date.and_time(*time)).earliest())
+    }
+}
+
+/// A command of a schedule, already checked against the command catalog
+#[derive(Debug)]
+pub struct Step<'a> {
+    pub command: &'a Command,
+    pub action: String,
+    pub parameters: Vec<String>,
+}
+
+impl fmt::Display for Step<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.command.command_name, self.action)?;
+        self.parameters.iter().try_for_each(|parameter| write!(f, " {}", parameter))
+    }
+}
+
+/// A `[[schedule]]` of the config, ready to run
+#[derive(Debug)]
+pub struct Schedule<'a> {
+    pub name: String,
+    pub at: String,
+    pub rule: Rule,
+    pub targets: Vec<Target>,
+    pub steps: Vec<Step<'a>>,
+    pub missed: MissedRuns,
+    pub grace: Duration,
+}
+
+/// Reads the schedules of the config, checking everything they need up front
+/// (instead of finding out at the time they run)
+pub fn load_schedules<'a>(config: &Config, commands: &'a [Command]) -> Result<Vec<Schedule<'a>>, Box<dyn Error>> {
+    let mut schedules = vec![];
+    for (i, settings) in config.schedules.iter().enumerate() {
+        let name = settings.name.clone().unwrap_or_else(|| format!("#{}", i + 1));
+
+        let mut targets = vec![];
+        for tv in &settings.tvs {
+            match config.groups.contains_key(tv) {
+                true => targets.extend(Target::resolve_group(tv, config)?),
+                false => targets.push(Target::resolve(tv, config)?),
+            }
+        }
+        if settings.tvs.is_empty() {
+            for tv in config.tvs.keys() {
+                targets.push(Target::resolve(tv, config)?);
+            }
+        }
+
+        if targets.is_empty() {
+            return Err(format!("The schedule {} has no TVs (and there are no TV profiles)", name).into());
+        }
+
+        let steps = settings
+            .run
+            .iter()
+            .map(|step| parse_step(step, commands))
+            .collect::<Result<_, _>>()?;
+        let grace = match &settings.grace {
+            Some(grace) => parse_duration(grace)?,
+            None => DEFAULT_GRACE,
+        };
+
+        schedules.push(Schedule {
+            name,
+            at: settings.at.clone(),
+            rule: settings.at.parse()?,
+            targets,
+            steps,
+            missed: settings.missed.unwrap_or(MissedRuns::Skip),
+            grace,
+        });
+    }
+    Ok(schedules)
+}
+
+fn parse_step<'a>(step: &str, commands: &'a [Command]) -> Result<Step<'a>, Box<dyn Error>> {
+    let words: Vec<&str> = step.split_whitespace().collect();
+    let (command_name, action) = match words.as_slice() {
+        [command_name, action, ..] => (*command_name, *action),
+        _ => return Err(format!("The scheduled command `{}` needs an action (e.g. power off)", step).into()),
+    };
+
+    let command = commands
+        .iter()
+        .find(|command| command.command_name == command_name)
+        .ok_or_else(|| format!("There's no command {} (in the scheduled `{}`)", command_name, step))?;
+    // favourite channels are only changed from the command line
+    let is_runnable = command.command_actions.iter().any(|(name, _)| *name == action)
+        && (command.command_name != "channel" || action == "set");
+    if !is_runnable {
+        return Err(Box::new(UnknownActionError::new(command_name, action)));
+    }
+
+    let parameters: Vec<String> = words[2..].iter().map(|word| word.to_string()).collect();
+    // the schedule is what says when it runs
+    if parameters.iter().any(|parameter| parameter == "--in") {
+        return Err(format!("The scheduled `{}` can't take --in (put it in the time of the schedule)", step).into());
+    }
+    check_action(command, action, &parameters).map_err(|e| format!("{} (in the scheduled `{}`)", e, step))?;
+
+    Ok(Step {
+        command,
+        action: action.to_string(),
+        parameters,
+    })
+}
+
+/// Runs the schedules forever. A run that is late (because the machine was asleep, or it wasn't
+/// running yet) is either skipped or caught up on, depending on the policy of its schedule.
+pub fn run_scheduler(schedules: &[Schedule], config: &Config, concurrency: usize) -> ! {
+    let app_catalog = AppCatalog::with_user_apps(&config.apps);
+    let started_at = Local::now();
+    let mut last_checked: Vec<DateTime<Local>> = schedules
+        .iter()
+        .map(|schedule| match schedule.missed {
+            // so that what was missed before starting is caught up on too
+            MissedRuns::CatchUp => TimeDelta::from_std(schedule.grace)
+                .ok()
+                .and_then(|grace| started_at.checked_sub_signed(grace))
+                .unwrap_or(started_at),
+            MissedRuns::Skip => started_at,
+        })
+        .collect();
+
+    thread::scope(|scope| loop {
+        let now = Local::now();
+        for (schedule, last_checked) in schedules.iter().zip(last_checked.iter_mut()) {
+            if let Some(due) = schedule.rule.latest_between(*last_checked, now) {
+                let late = (now - due).to_std().unwrap_or_default();
+                if late <= ON_TIME || (schedule.missed == MissedRuns::CatchUp && late <= schedule.grace) {
+                    // each one on a thread of its own, since waking TVs up and waiting for them takes a while
+                    let app_catalog = &app_catalog;
+                    scope.spawn(move || run_schedule(schedule, due, config, app_catalog, concurrency));
+                } else {
+                    eprintln!(
+                        "Skipped {} of {} ({} minutes late, and it doesn't catch up on missed runs)",
+                        schedule.name,
+                        due.format("%Y-%m-%d %H:%M"),
+                        late.as_secs() / 60
+                    );
+                }
+            }
+            *last_checked = now;
+        }
+
+        let next_run = schedules.iter().filter_map(|schedule| schedule.rule.next_after(now)).min();
+        let until_next_run = next_run
+            .and_then(|next_run| (next_run - Local::now()).to_std().ok())
+            .unwrap_or_default();
+        // a little past it, so that it's due when we wake up
+        sleep((until_next_run + Duration::from_millis(100)).min(MAX_SLEEP));
+    })
+}
+
+/// Runs the steps of a schedule on every TV of it, where a TV whose step failed (e.g. it didn't wake up)
+/// doesn't get the steps after it
+fn run_schedule(
+    schedule: &Schedule,
+    due: DateTime<Local>,
+    config: &Config,
+    app_catalog: &AppCatalog,
+    concurrency: usize,
+) {
+    let results = run_concurrently(&schedule.targets, concurrency, |target| {
+        let mut lines = vec![];
+        for step in &schedule.steps {
+            match run_action(step.command, &step.action, &step.parameters, target, config, app_catalog) {
+                Ok(responses) => lines.push(format!("{}: {}: {}", target.name, step, responses.join(", "))),
+                Err(e) => {
+                    lines.push(format!("{}: {}: failed ({})", target.name, step, e));
+                    break;
+                }
+            }
+        }
+        lines
+    });
+
+    // all at once, so that the lines of different schedules don't get mixed up
+    let time = due.format("%Y-%m-%d %H:%M");
+    let report: Vec<String> = results
+        .iter()
+        .flatten()
+        .map(|line| format!("[{}] {}: {}", time, schedule.name, line))
+        .collect();
+    println!("{}", report.join("\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::generate_command_definitions;
+    use chrono_tz::{Europe::Berlin, Tz};
+
+    /// The rules run in local time, so the tests use a zone with DST instead: in 2026 Berlin skips
+    /// from 02:00 to 03:00 on March 29, and goes back from 03:00 to 02:00 on October 25
+    fn berlin(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Tz> {
+        Berlin.with_ymd_and_hms(year, month, day, hour, minute, 0).earliest().unwrap()
+    }
+
+    fn rule(rule: &str) -> Rule {
+        rule.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45m").unwrap(), Duration::from_secs(45 * 60));
+        assert_eq!(parse_duration(" 1h30m ").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_duration("2d").unwrap(), Duration::from_secs(2 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for duration in ["", "0s", "90", "1h30", "m", "5x", "-5m", "1.5h"] {
+            assert!(parse_duration(duration).is_err(), "{} should be invalid", duration);
+        }
+    }
+
+    #[test]
+    fn rejects_durations_that_overflow() {
+        assert!(parse_duration("99999999999999999d").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn parses_rules() {
+        let weekdays = rule("weekdays 08:00");
+        assert_eq!(weekdays.days, [true, true, true, true, true, false, false]);
+        assert_eq!(weekdays.times, [NaiveTime::from_hms_opt(8, 0, 0).unwrap()]);
+
+        let some_days = rule("mon,wed,fri 18:30,12:00, 12:00");
+        assert_eq!(some_days.days, [true, false, true, false, true, false, false]);
+        assert_eq!(
+            some_days.times,
+            [NaiveTime::from_hms_opt(12, 0, 0).unwrap(), NaiveTime::from_hms_opt(18, 30, 0).unwrap()]
+        );
+
+        assert_eq!(rule("22:30").days, [true; 7]);
+        assert_eq!(rule("Weekends 10:00").days, rule("sat-sun 10:00").days);
+        // a range can go past Sunday
+        assert_eq!(rule("fri-mon 10:00").days, [true, false, false, false, true, true, true]);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for invalid in ["", "weekdays", "25:00", "8am", "funday 10:00", "mon-funday 10:00"] {
+            assert!(invalid.parse::<Rule>().is_err(), "{} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_steps() {
+        let commands = generate_command_definitions();
+        for step in [
+            "power off",
+            "power on --wait",
+            "power on --wait 90",
+            "set volume 10",
+            "channel set news",
+            "app launch netflix",
+            "input hdmi-1",
+        ] {
+            assert!(parse_step(step, &commands).is_ok(), "{} should be valid", step);
+        }
+
+        let step = parse_step("set volume 10", &commands).unwrap();
+        assert_eq!((step.command.command_name, step.action.as_str()), ("set", "volume"));
+        assert_eq!(step.parameters, ["10"]);
+    }
+
+    #[test]
+    fn rejects_invalid_steps() {
+        let commands = generate_command_definitions();
+        for step in [
+            "",
+            "power",
+            "teleport off",
+            "power sideways",
+            "channel favourite news 7-1",
+            "power off --in 45m",
+            "power on --in 1h",
+            "power on --wait -1",
+            "power on --wait inf",
+            "power off now",
+            "set volume",
+            "set volume 10 20",
+            "channel set",
+        ] {
+            assert!(parse_step(step, &commands).is_err(), "{} should be invalid", step);
+        }
+    }
+
+    #[test]
+    fn next_run_is_strictly_after() {
+        let rule = rule("mon,wed 08:00,20:00");
+        // Monday, May 4 2026
+        assert_eq!(rule.next_after(berlin(2026, 5, 4, 7, 0)), Some(berlin(2026, 5, 4, 8, 0)));
+        assert_eq!(rule.next_after(berlin(2026, 5, 4, 8, 0)), Some(berlin(2026, 5, 4, 20, 0)));
+        assert_eq!(rule.next_after(berlin(2026, 5, 4, 21, 0)), Some(berlin(2026, 5, 6, 8, 0)));
+        // from Wednesday night to the next Monday
+        assert_eq!(rule.next_after(berlin(2026, 5, 6, 21, 0)), Some(berlin(2026, 5, 11, 8, 0)));
+    }
+
+    #[test]
+    fn latest_run_is_the_last_one_in_between() {
+        let rule = rule("daily 08:00,20:00");
+        let latest = |after, until| rule.latest_between(after, until);
+        assert_eq!(latest(berlin(2026, 5, 4, 7, 0), berlin(2026, 5, 4, 7, 59)), None);
+        assert_eq!(latest(berlin(2026, 5, 4, 7, 0), berlin(2026, 5, 4, 8, 0)), Some(berlin(2026, 5, 4, 8, 0)));
+        // the one it was last checked at already ran
+        assert_eq!(latest(berlin(2026, 5, 4, 8, 0), berlin(2026, 5, 4, 9, 0)), None);
+        // asleep for two days, only the last one is due
+        assert_eq!(latest(berlin(2026, 5, 2, 9, 0), berlin(2026, 5, 4, 9, 0)), Some(berlin(2026, 5, 4, 8, 0)));
+        assert_eq!(latest(berlin(2026, 5, 3, 19, 0), berlin(2026, 5, 4, 7, 0)), Some(berlin(2026, 5, 3, 20, 0)));
+    }
+
+    #[test]
+    fn a_time_skipped_by_dst_doesnt_run_that_day() {
+        let rule = rule("daily 02:30");
+        let day_after = berlin(2026, 3, 30, 2, 30);
+        assert_eq!(rule.next_after(berlin(2026, 3, 29, 0, 0)), Some(day_after));
+        assert_eq!(rule.latest_between(berlin(2026, 3, 29, 0, 0), berlin(2026, 3, 29, 12, 0)), None);
+        // but the times around it still do
+        let rule = "daily 01:30,03:30".parse::<Rule>().unwrap();
+        assert_eq!(rule.next_after(berlin(2026, 3, 29, 1, 30)), Some(berlin(2026, 3, 29, 3, 30)));
+    }
+
+    #[test]
+    fn a_time_repeated_by_dst_runs_once() {
+        let rule = rule("daily 02:30");
+        let (midnight, next_midnight) = (berlin(2026, 10, 25, 0, 0), berlin(2026, 10, 26, 0, 0));
+        let mut runs = vec![];
+        let mut after = midnight;
+        while let Some(run) = rule.next_after(after).filter(|run| *run < next_midnight) {
+            runs.push(run);
+            after = run;
+        }
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].format("%H:%M").to_string(), "02:30");
+        assert_eq!(rule.next_after(runs[0]), Some(berlin(2026, 10, 26, 2, 30)));
+        assert_eq!(rule.latest_between(midnight, next_midnight), Some(runs[0]));
+    }
+}
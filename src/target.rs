@@ -0,0 +1,138 @@
+use log::debug;
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+use crate::bytes::Salt;
+use crate::comm::send_command;
+use crate::config::Config;
+#[cfg(all(unix, feature = "daemon"))]
+use crate::daemon::send_through_daemon;
+use crate::logging::{Hex, Secret};
+use crate::metrics::record_command;
+
+pub use lgster_core::{DEFAULT_PORT, DEFAULT_SALT};
+
+#[derive(Debug)]
+pub struct MissingKeycodeError {
+    target_name: String,
+}
+
+impl fmt::Display for MissingKeycodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No keycode for {} (use --keycode or set it in its TV profile)",
+            self.target_name
+        )
+    }
+}
+
+impl Error for MissingKeycodeError {}
+
+#[derive(Debug)]
+pub struct UnknownGroupError {
+    group_name: String,
+}
+
+impl fmt::Display for UnknownGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "There's no group {} in the config file", self.group_name)
+    }
+}
+
+impl Error for UnknownGroupError {}
+
+/// A TV to send commands to, either given directly by its host or by the name of its TV profile
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// How the TV was referred to (the profile name or the host)
+    pub name: String,
+    /// The name of the TV profile the target came from, if any
+    pub profile: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub keycode: Option<String>,
+    pub salt: [u8; 16],
+}
+
+impl Target {
+    /// Resolves `name` to a TV profile from the config or, if there's none with that name,
+    /// to a target with `name` as its host and the default settings
+    pub fn resolve(name: &str, config: &Config) -> Result<Target, Box<dyn Error>> {
+        let tv_profile = match config.tvs.get(name) {
+            Some(tv_profile) => tv_profile,
+            None => {
+                debug!("resolved target={} to host={} (no TV profile)", name, name);
+                return Ok(Target {
+                    name: name.to_string(),
+                    profile: None,
+                    host: name.to_string(),
+                    port: DEFAULT_PORT,
+                    keycode: None,
+                    salt: DEFAULT_SALT,
+                })
+            }
+        };
+
+        let salt = match &tv_profile.salt {
+            Some(salt) => salt.parse::<Salt>()?.0,
+            None => DEFAULT_SALT,
+        };
+
+        debug!(
+            "resolved target={} to host={} port={} keycode={} salt={} from its TV profile",
+            name,
+            tv_profile.host,
+            tv_profile.port.unwrap_or(DEFAULT_PORT),
+            match &tv_profile.keycode {
+                Some(keycode) => Secret(keycode).to_string(),
+                None => "none".to_string(),
+            },
+            Hex(&salt)
+        );
+        Ok(Target {
+            name: name.to_string(),
+            profile: Some(name.to_string()),
+            host: tv_profile.host.clone(),
+            port: tv_profile.port.unwrap_or(DEFAULT_PORT),
+            keycode: tv_profile.keycode.clone(),
+            salt,
+        })
+    }
+
+    /// Resolves every member of a `[groups]` entry of the config
+    pub fn resolve_group(group_name: &str, config: &Config) -> Result<Vec<Target>, Box<dyn Error>> {
+        let members = config.groups.get(group_name).ok_or_else(|| UnknownGroupError {
+            group_name: group_name.to_string(),
+        })?;
+
+        members
+            .iter()
+            .map(|member| Target::resolve(member, config))
+            .collect()
+    }
+
+    pub fn keycode(&self) -> Result<&str, MissingKeycodeError> {
+        self.keycode.as_deref().ok_or_else(|| MissingKeycodeError {
+            target_name: self.name.clone(),
+        })
+    }
+
+    pub fn send(&self, command: String) -> Result<String, Box<dyn Error>> {
+        let keycode = self.keycode()?;
+        let started_at = Instant::now();
+        #[cfg(all(unix, feature = "daemon"))]
+        let forwarded = send_through_daemon(self, keycode, &command);
+        #[cfg(not(all(unix, feature = "daemon")))]
+        let forwarded = None;
+
+        let result = match forwarded {
+            Some(result) => result,
+            None => send_command(&self.host, self.port, keycode, &self.salt, command),
+        };
+        // the ones forwarded to the daemon too, whose metrics are its own
+        record_command(&self.name, started_at.elapsed(), result.as_ref().err().map(|e| e.as_ref()));
+        result
+    }
+}
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "record")]
+use std::collections::{HashMap, VecDeque};
+#[cfg(feature = "record")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "record")]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(any(feature = "record", all(unix, feature = "daemon")))]
+use std::io::ErrorKind;
+use std::io::{self, Error as IOError};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "record")]
+use std::path::Path;
+#[cfg(feature = "record")]
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::logging::Hex;
+use crate::network;
+
+/// How the encrypted messages get to the TVs and back. It's the network by default, but it can be
+/// swapped (once, before the first command) for one that records or replays the exchanges.
+pub trait Transport: Send + Sync {
+    /// Sends the encrypted `request` for the plaintext `command` to the TV, returning its encrypted response
+    fn exchange(&self, host: &str, port: u16, command: &str, request: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Called with the whole exchange once the response is decrypted (or the exchange failed)
+    fn exchanged(&self, _exchange: &Exchange) {}
+
+    /// Whether the TV accepts connections at all, which is quicker to find out than sending it a command
+    fn accepts_connections(&self, host: &str, port: u16) -> bool {
+        let address = match (host, port).to_socket_addrs() {
+            Ok(mut addresses) => addresses.next(),
+            Err(_) => None,
+        };
+        address.is_some_and(|address| TcpStream::connect_timeout(&address, Duration::from_secs(1)).is_ok())
+    }
+}
+
+/// Everything about a command sent to a TV, as it's saved to a fixture file (one JSON object per line)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub host: String,
+    pub port: u16,
+    /// The plaintext command (e.g. `CURRENT_VOL`)
+    pub command: String,
+    /// The encrypted request, as hex
+    pub request: String,
+    /// The encrypted response, as hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    /// The decrypted reply (e.g. `VOL:5\n`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<String>,
+    /// Why there's no response (e.g. the TV was off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
+    pub took_ms: f64,
+}
+
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn exchange(&self, host: &str, port: u16, _command: &str, request: &[u8]) -> io::Result<Vec<u8>> {
+        network::send_and_receive_tcp_message((host, port), request)
+    }
+}
+
+/// Talks to the TVs over the network, saving every exchange to a fixture file
+#[cfg(feature = "record")]
+pub struct RecordingTransport {
+    file: Mutex<File>,
+}
+
+#[cfg(feature = "record")]
+impl RecordingTransport {
+    /// Appends to `path`, so that many sessions can be recorded to the same file
+    pub fn create(path: &Path) -> io::Result<RecordingTransport> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RecordingTransport { file: Mutex::new(file) })
+    }
+}
+
+#[cfg(feature = "record")]
+impl Transport for RecordingTransport {
+    fn exchange(&self, host: &str, port: u16, command: &str, request: &[u8]) -> io::Result<Vec<u8>> {
+        TcpTransport.exchange(host, port, command, request)
+    }
+
+    fn exchanged(&self, exchange: &Exchange) {
+        // serializing this plain struct can't fail
+        let mut line = serde_json::to_string(exchange).unwrap();
+        line.push('\n');
+        // one write per exchange (flushed right away), so that a killed process still leaves whole lines
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("Couldn't record an exchange with {} ({})", exchange.host, e);
+        }
+    }
+}
+
+#[cfg(feature = "record")]
+type RecordedOutcome = Result<Vec<u8>, (ErrorKind, String)>;
+
+/// Serves the exchanges of a fixture file back instead of talking to the TVs. The exchanges of each
+/// command to each TV are served in the order they were recorded, with the last one repeated forever.
+/// Since the responses were encrypted by the TV, the keycode must be the same as when recording.
+#[cfg(feature = "record")]
+pub struct ReplayTransport {
+    exchanges: Mutex<HashMap<(String, u16, String), VecDeque<RecordedOutcome>>>,
+}
+
+#[cfg(feature = "record")]
+impl ReplayTransport {
+    pub fn open(path: &Path) -> io::Result<ReplayTransport> {
+        let mut exchanges: HashMap<_, VecDeque<_>> = HashMap::new();
+        for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid_line = |reason: String| {
+                IOError::new(
+                    ErrorKind::InvalidData,
+                    format!("line {} of {} is invalid ({})", i + 1, path.display(), reason),
+                )
+            };
+
+            let exchange: Exchange = serde_json::from_str(&line).map_err(|e| invalid_line(e.to_string()))?;
+            let outcome = match (&exchange.response, &exchange.error) {
+                (Some(response), _) => {
+                    Ok(from_hex(response).ok_or_else(|| invalid_line("the response isn't hex".to_string()))?)
+                }
+                (None, Some(error)) => Err((error_kind_from_name(exchange.error_kind.as_deref()), error.clone())),
+                (None, None) => return Err(invalid_line("it has neither a response nor an error".to_string())),
+            };
+            exchanges
+                .entry((exchange.host, exchange.port, exchange.command))
+                .or_default()
+                .push_back(outcome);
+        }
+        Ok(ReplayTransport {
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+}
+
+#[cfg(feature = "record")]
+impl Transport for ReplayTransport {
+    fn exchange(&self, host: &str, port: u16, command: &str, _request: &[u8]) -> io::Result<Vec<u8>> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let outcomes = exchanges
+            .get_mut(&(host.to_string(), port, command.to_string()))
+            .filter(|outcomes| !outcomes.is_empty())
+            .ok_or_else(|| {
+                IOError::other(format!("there's no recorded exchange of {} with {}:{}", command, host, port))
+            })?;
+
+        let outcome = match outcomes.len() {
+            1 => outcomes[0].clone(),
+            // this can't be None, since it's not empty
+            _ => outcomes.pop_front().unwrap(),
+        };
+        outcome.map_err(|(kind, error)| IOError::new(kind, error))
+    }
+
+    // the replayed exchange of the command that follows tells
+    fn accepts_connections(&self, _host: &str, _port: u16) -> bool {
+        true
+    }
+}
+
+static TRANSPORT: OnceLock<Box<dyn Transport>> = OnceLock::new();
+
+/// Swaps the transport of the process, which can only be done before the first command is sent.
+/// Returns whether it was swapped.
+pub fn set_transport(transport: Box<dyn Transport>) -> bool {
+    TRANSPORT.set(transport).is_ok()
+}
+
+/// The transport of the process
+pub fn transport() -> &'static dyn Transport {
+    TRANSPORT.get_or_init(|| Box::new(TcpTransport)).as_ref()
+}
+
+/// An exchange for the transport, from the pieces `comm` has at hand
+pub(crate) fn exchange_of(
+    host: &str,
+    port: u16,
+    command: &str,
+    request: &[u8],
+    outcome: Result<(&[u8], &str), &IOError>,
+    took: Duration,
+) -> Exchange {
+    let (response, reply, error, error_kind) = match outcome {
+        Ok((response, reply)) => (Some(Hex(response).to_string()), Some(reply.to_string()), None, None),
+        Err(e) => (None, None, Some(e.to_string()), Some(format!("{:?}", e.kind()))),
+    };
+    Exchange {
+        host: host.to_string(),
+        port,
+        command: command.to_string(),
+        request: Hex(request).to_string(),
+        response,
+        reply,
+        error,
+        error_kind,
+        // to the microsecond
+        took_ms: (took.as_secs_f64() * 1_000_000.0).round() / 1000.0,
+    }
+}
+
+#[cfg(feature = "record")]
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The kinds of the errors that are worth telling apart (see `ErrorClass`), by their Debug names
+#[cfg(any(feature = "record", all(unix, feature = "daemon")))]
+pub(crate) fn error_kind_from_name(name: Option<&str>) -> ErrorKind {
+    match name {
+        Some("TimedOut") => ErrorKind::TimedOut,
+        Some("WouldBlock") => ErrorKind::WouldBlock,
+        Some("ConnectionRefused") => ErrorKind::ConnectionRefused,
+        Some("ConnectionReset") => ErrorKind::ConnectionReset,
+        Some("UnexpectedEof") => ErrorKind::UnexpectedEof,
+        Some("InvalidInput") => ErrorKind::InvalidInput,
+        _ => ErrorKind::Other,
+    }
+}
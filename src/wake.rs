@@ -0,0 +1,188 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::arp::mac_address_of;
+use crate::bytes::MacAddress;
+use crate::comm::{send_wol_packet, WakeOptions};
+use crate::config::Config;
+use crate::metrics::record_wake_packets;
+use crate::target::Target;
+use crate::transport::transport;
+
+/// Where the wake-on-lan packet is sent when no target is given
+pub const DEFAULT_WAKE_TARGET: &str = "255.255.255.255";
+/// The IPv6 counterpart of the broadcast, which needs an interface to go with it
+pub const DEFAULT_IPV6_WAKE_TARGET: &str = "ff02::1";
+/// The longest wait before sending the wake-on-lan packet again while waiting for a TV
+const MAX_WAKE_RESEND_INTERVAL: Duration = Duration::from_secs(16);
+
+#[derive(Debug)]
+pub struct UnknownMacAddressError {
+    tv: String,
+}
+
+impl fmt::Display for UnknownMacAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            concat!(
+                "Couldn't find the MAC address of {} (`query mac-addresses` saves it into the TV profile ",
+                "while the TV is on, but it can also be given explicitly)"
+            ),
+            self.tv
+        )
+    }
+}
+
+impl Error for UnknownMacAddressError {}
+
+#[derive(Debug)]
+pub struct WakeTimeoutError {
+    tv: String,
+    waited: Duration,
+}
+
+impl fmt::Display for WakeTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} didn't answer after {:.1}s",
+            self.tv,
+            self.waited.as_secs_f64()
+        )
+    }
+}
+
+impl Error for WakeTimeoutError {}
+
+/// Finds a MAC address anywhere in a reply (e.g. to `GET_MACADDRESS wired`), normalized to lowercase
+pub fn mac_address_from_reply(reply: &str) -> Option<String> {
+    const MAC_ADDRESS_LENGTH: usize = 17;
+
+    (0..reply.len().saturating_sub(MAC_ADDRESS_LENGTH - 1))
+        .filter_map(|i| reply.get(i..i + MAC_ADDRESS_LENGTH))
+        .find(|candidate| {
+            candidate.parse::<MacAddress>().is_ok()
+                && candidate.chars().all(|c| c == ':' || c.is_ascii_hexdigit())
+        })
+        .map(str::to_lowercase)
+}
+
+/// The IPv4 address of a host, resolving it if it's a name
+pub fn resolve_ipv4(host: &str) -> Option<Ipv4Addr> {
+    (host, 0).to_socket_addrs().ok()?.find_map(|address| match address.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    })
+}
+
+/// The MAC address the kernel last saw for a host (only useful right after talking to it)
+pub fn learn_mac_address(host: &str) -> Option<String> {
+    mac_address_of(resolve_ipv4(host)?).map(|mac_address| mac_address.to_lowercase())
+}
+
+/// The MAC address of a TV given by its profile name or by its host: the one cached in
+/// its profile or, failing that, whatever the ARP table has for it
+pub fn mac_address_for(tv: &str, config: &Config) -> Result<String, UnknownMacAddressError> {
+    let (cached, host) = match config.tvs.get(tv) {
+        Some(tv_profile) => (tv_profile.mac.clone(), tv_profile.host.as_str()),
+        None => (None, tv),
+    };
+
+    cached
+        .or_else(|| learn_mac_address(host))
+        .ok_or_else(|| UnknownMacAddressError { tv: tv.to_string() })
+}
+
+/// Parses a SecureOn password, which is written just like a MAC address
+pub fn parse_secure_on_password(password: &str) -> Result<[u8; 6], Box<dyn Error>> {
+    Ok(password.parse::<MacAddress>()?.0)
+}
+
+/// Where the wake-on-lan packet of a TV should be sent and how, from the `wake` table of its
+/// profile. A TV that isn't a profile (or one without that table) gets the defaults.
+pub fn wake_options_for(
+    tv: &str,
+    config: &Config,
+) -> Result<(Option<String>, WakeOptions), Box<dyn Error>> {
+    let defaults = WakeOptions::default();
+    let settings = match config.tvs.get(tv).and_then(|tv_profile| tv_profile.wake.clone()) {
+        Some(settings) => settings,
+        None => return Ok((None, defaults)),
+    };
+
+    let password = match &settings.password {
+        Some(password) => Some(parse_secure_on_password(password)?),
+        None => None,
+    };
+    Ok((
+        settings.target,
+        WakeOptions {
+            port: settings.port.unwrap_or(defaults.port),
+            password,
+            repeat: settings.repeat.unwrap_or(defaults.repeat),
+            interval: settings
+                .interval_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.interval),
+            interface: settings.interface,
+            source_address: settings.source_address,
+        },
+    ))
+}
+
+/// Sends the wake-on-lan packet to a TV, with the MAC address and the wake settings of its profile
+pub fn wake_up(target: &Target, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mac_address = mac_address_for(&target.name, config)?;
+    let (target_ip, options) = wake_options_for(&target.name, config)?;
+    let target_ip = target_ip.unwrap_or_else(|| DEFAULT_WAKE_TARGET.to_string());
+
+    send_wol_packet(&target_ip, &mac_address, &options)?;
+    record_wake_packets(&target.name, options.repeat.max(1));
+    Ok(())
+}
+
+/// Wakes a TV up and waits until its IP Control answers, returning how long that took.
+/// The packet is sent again now and then (less and less often), since a single one is easily lost.
+pub fn wake_up_and_wait(
+    target: &Target,
+    config: &Config,
+    timeout: Duration,
+) -> Result<Duration, Box<dyn Error>> {
+    let started_at = Instant::now();
+    let deadline = started_at + timeout;
+    let mut backoff = Duration::from_millis(500);
+    let mut resend_at = started_at;
+    let mut resend_interval = Duration::from_secs(2);
+
+    loop {
+        if Instant::now() >= resend_at {
+            wake_up(target, config)?;
+            resend_at = Instant::now() + resend_interval;
+            resend_interval = (resend_interval * 2).min(MAX_WAKE_RESEND_INTERVAL);
+        }
+        if is_answering(target) {
+            return Ok(started_at.elapsed());
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Box::new(WakeTimeoutError {
+                tv: target.name.clone(),
+                waited: started_at.elapsed(),
+            }));
+        }
+        sleep(backoff.min(deadline - now));
+        backoff = (backoff * 3 / 2).min(Duration::from_secs(5));
+    }
+}
+
+/// Whether the TV accepts connections on the IP Control port and answers a harmless query, both
+/// asked through the transport (so that a replay doesn't touch the network).
+/// The port usually opens a little before the TV is actually ready to answer.
+fn is_answering(target: &Target) -> bool {
+    transport().accepts_connections(&target.host, target.port) && target.send("CURRENT_VOL".to_string()).is_ok()
+}
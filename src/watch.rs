@@ -0,0 +1,174 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::actions::run_action;
+use crate::apps::AppCatalog;
+use crate::commands::Command;
+use crate::config::Config;
+use crate::error::ErrorClass;
+use crate::target::Target;
+
+/// The property that tells whether the TV answers at all, which comes from polling the others
+pub const REACHABLE: &str = "reachable";
+// the queries that aren't about the current state of the TV
+const NOT_STATE: &[&str] = &["mac-addresses"];
+
+/// A property of a TV that changed, or that became known for the first time (without an `old` value)
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    pub tv: String,
+    pub property: String,
+    pub old: Option<String>,
+    pub new: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: f64,
+}
+
+/// The properties that can be watched, which are the actions of `query` that are about the
+/// current state of the TV. The screen state isn't one of them: IP Control has no query for it
+/// (`SCREEN_MUTE` only sets it), and remembering the last `screen` command sent would be wrong as
+/// soon as the remote or turning the TV off and on changes it.
+pub fn watchable_properties(query: &Command) -> Vec<&'static str> {
+    query
+        .command_actions
+        .iter()
+        .map(|(action, _)| *action)
+        .filter(|action| !NOT_STATE.contains(action))
+        .collect()
+}
+
+struct WatchedProperty {
+    name: &'static str,
+    interval: Duration,
+    next_poll: Instant,
+}
+
+/// Polls the properties of a TV, each one at its own interval, and keeps their last known values
+pub struct Watcher<'a> {
+    target: &'a Target,
+    query: &'a Command,
+    config: &'a Config,
+    app_catalog: &'a AppCatalog,
+    properties: Vec<WatchedProperty>,
+    state: BTreeMap<&'static str, String>,
+}
+
+impl<'a> Watcher<'a> {
+    /// Watches every property of `query` at `default_interval`, unless it has an interval of its
+    /// own in `intervals`. A property with an interval of zero isn't watched at all.
+    pub fn new(
+        target: &'a Target,
+        query: &'a Command,
+        config: &'a Config,
+        app_catalog: &'a AppCatalog,
+        default_interval: Duration,
+        intervals: &BTreeMap<String, Duration>,
+    ) -> Self {
+        let now = Instant::now();
+        let properties = watchable_properties(query)
+            .into_iter()
+            .map(|name| WatchedProperty {
+                name,
+                interval: intervals.get(name).copied().unwrap_or(default_interval),
+                next_poll: now,
+            })
+            .filter(|property| !property.interval.is_zero())
+            .collect();
+
+        Watcher {
+            target,
+            query,
+            config,
+            app_catalog,
+            properties,
+            state: BTreeMap::new(),
+        }
+    }
+
+    /// Polls the properties that are due, returning what changed
+    pub fn poll_due(&mut self) -> Vec<StateChange> {
+        let now = Instant::now();
+        let mut changes = vec![];
+        let mut reachable = None;
+
+        for i in 0..self.properties.len() {
+            if self.properties[i].next_poll > now {
+                continue;
+            }
+            self.properties[i].next_poll = now + self.properties[i].interval;
+            // there's no point in waiting for it again in the same round
+            if reachable == Some(false) {
+                continue;
+            }
+
+            let name = self.properties[i].name;
+            match run_action(self.query, name, &[], self.target, self.config, self.app_catalog) {
+                Ok(responses) => {
+                    reachable = Some(true);
+                    if let Some(reply) = responses.first() {
+                        self.update(name, reply_value(reply), &mut changes);
+                    }
+                }
+                Err(e) => match ErrorClass::of(e.as_ref()) {
+                    ErrorClass::Unreachable | ErrorClass::Timeout => reachable = Some(false),
+                    // it answered, it just didn't like the question
+                    _ => reachable = Some(true),
+                },
+            }
+        }
+
+        if let Some(reachable) = reachable {
+            self.update(REACHABLE, reachable.to_string(), &mut changes);
+        }
+        changes
+    }
+
+    /// When the next property is due
+    pub fn next_poll(&self) -> Instant {
+        self.properties
+            .iter()
+            .map(|property| property.next_poll)
+            .min()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(60))
+    }
+
+    /// Makes every property due, e.g. right after sending a command that changes them
+    pub fn poll_everything_now(&mut self) {
+        let now = Instant::now();
+        self.properties.iter_mut().for_each(|property| property.next_poll = now);
+    }
+
+    /// The last known value of every property
+    pub fn state(&self) -> &BTreeMap<&'static str, String> {
+        &self.state
+    }
+
+    fn update(&mut self, property: &'static str, value: String, changes: &mut Vec<StateChange>) {
+        let old = self.state.get(property);
+        if old == Some(&value) {
+            return;
+        }
+
+        changes.push(StateChange {
+            tv: self.target.name.clone(),
+            property: property.to_string(),
+            old: old.cloned(),
+            new: value.clone(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_secs_f64())
+                .unwrap_or_default(),
+        });
+        self.state.insert(property, value);
+    }
+}
+
+/// The value of a reply to a query (e.g. `5` from `VOL:5`)
+fn reply_value(reply: &str) -> String {
+    let value = match reply.find(':') {
+        Some(i) => &reply[i + 1..],
+        None => reply,
+    };
+    value.trim().to_string()
+}
@@ -0,0 +1,71 @@
+//! Builds tests/ffi/client.c against the cdylib and runs it against a fake TV
+#![cfg(feature = "ffi")]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use lgster::comm::{aes_key_for, decrypt_command, encrypt_reply};
+use lgster::target::DEFAULT_SALT;
+
+/// Answers `CURRENT_VOL` with `VOL:5` (and anything else with `NG`), returning its port
+fn start_fake_tv() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let aes_key = aes_key_for("ABCDEFGH", &DEFAULT_SALT);
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = vec![0; 32];
+            if stream.read_exact(&mut request).is_err() {
+                continue;
+            }
+            let reply = match decrypt_command(&request, &aes_key).as_deref() {
+                Some("CURRENT_VOL") => "VOL:5",
+                _ => "NG",
+            };
+            let _ = stream.write_all(&encrypt_reply(reply, &[7; 16], &aes_key));
+        }
+    });
+    port
+}
+
+/// Builds the cdylib with the ffi feature into a target directory of its own, since the one of
+/// the tests only has the rlib (and whatever the last build left there), returning where it is
+fn build_cdylib() -> PathBuf {
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cdylib");
+    let built = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["rustc", "--lib", "--features", "ffi", "--crate-type", "cdylib", "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .expect("Couldn't run cargo");
+    assert!(built.success(), "the cdylib didn't build");
+    target_dir.join("debug")
+}
+
+#[test]
+fn c_program_uses_the_c_api() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let library_dir = build_cdylib();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("lgster-ffi-client");
+
+    let compiled = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(manifest_dir.join("tests/ffi/client.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .args(["-llgster", "-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .status()
+        .expect("Couldn't run the C compiler");
+    assert!(compiled.success(), "the C program didn't compile");
+
+    let port = start_fake_tv();
+    let ran = Command::new(&program).arg(port.to_string()).status().unwrap();
+    assert!(ran.success(), "the C program failed (see its output)");
+}
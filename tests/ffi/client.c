@@ -0,0 +1,76 @@
+/* Exercises the C API against the fake TV of tests/ffi.rs, whose port is the only argument */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "lgster.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                                  \
+    do {                                                                                  \
+        if (!(condition)) {                                                               \
+            const char *error = lgster_last_error();                                      \
+            fprintf(stderr, "%s:%d: %s failed (last error: %s)\n", __FILE__, __LINE__,    \
+                    #condition, error ? error : "none");                                  \
+            failures++;                                                                   \
+        }                                                                                 \
+    } while (0)
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s PORT\n", argv[0]);
+        return 2;
+    }
+    uint16_t port = (uint16_t)atoi(argv[1]);
+
+    LgsterClient *client = NULL;
+    char *reply = NULL;
+    CHECK(lgster_client_new("127.0.0.1", port, "ABCDEFGH", NULL, &client) == LGSTER_STATUS_OK);
+    CHECK(client != NULL);
+    CHECK(lgster_last_error() == NULL);
+
+    CHECK(lgster_send(client, "CURRENT_VOL", &reply) == LGSTER_STATUS_OK);
+    CHECK(reply != NULL && strcmp(reply, "VOL:5") == 0);
+    lgster_string_free(reply);
+
+    /* the same salt as the default one, given explicitly */
+    LgsterClient *salted = NULL;
+    CHECK(lgster_client_new("127.0.0.1", port, "ABCDEFGH", "63-61-b8-0e-9b-dc-a6-63-8d-07-20-f2-cc-56-8f-b9",
+                            &salted) == LGSTER_STATUS_OK);
+    CHECK(lgster_send(salted, "CURRENT_VOL", &reply) == LGSTER_STATUS_OK);
+    CHECK(reply != NULL && strcmp(reply, "VOL:5") == 0);
+    lgster_string_free(reply);
+    lgster_client_free(salted);
+
+    /* invalid arguments */
+    LgsterClient *invalid = (LgsterClient *)&failures;
+    CHECK(lgster_client_new("127.0.0.1", port, "ABCDEFGH", "not-a-salt", &invalid) == LGSTER_STATUS_INVALID_REQUEST);
+    CHECK(invalid == NULL);
+    CHECK(lgster_last_error() != NULL);
+    CHECK(lgster_client_new(NULL, port, "ABCDEFGH", NULL, &invalid) == LGSTER_STATUS_INVALID_REQUEST);
+    CHECK(lgster_send(client, NULL, &reply) == LGSTER_STATUS_INVALID_REQUEST);
+    CHECK(reply == NULL);
+
+    /* no keycode */
+    LgsterClient *without_keycode = NULL;
+    CHECK(lgster_client_new("127.0.0.1", port, NULL, NULL, &without_keycode) == LGSTER_STATUS_OK);
+    CHECK(lgster_send(without_keycode, "CURRENT_VOL", &reply) == LGSTER_STATUS_NOT_CONFIGURED);
+    lgster_client_free(without_keycode);
+
+    /* nothing listens on port 1 */
+    LgsterClient *unreachable = NULL;
+    CHECK(lgster_client_new("127.0.0.1", 1, "ABCDEFGH", NULL, &unreachable) == LGSTER_STATUS_OK);
+    CHECK(lgster_send(unreachable, "CURRENT_VOL", &reply) == LGSTER_STATUS_UNREACHABLE);
+    lgster_client_free(unreachable);
+
+    CHECK(lgster_wake("a8:23:fe:01:02:03", "127.0.0.1") == LGSTER_STATUS_OK);
+    CHECK(lgster_wake("not a mac address", "127.0.0.1") == LGSTER_STATUS_INVALID_REQUEST);
+    CHECK(lgster_wake(NULL, NULL) == LGSTER_STATUS_INVALID_REQUEST);
+
+    lgster_client_free(client);
+    lgster_client_free(NULL);
+    lgster_string_free(NULL);
+
+    return failures == 0 ? 0 : 1;
+}